@@ -39,7 +39,44 @@ fn build_alkane(wasm_str: &str, features: Vec<&'static str>) -> Result<()> {
     }
 }
 
+// PANDA_ROLL_BUILD_ID if set, otherwise the commit being built, for GetVersion
+fn build_id() -> String {
+    if let Ok(id) = env::var("PANDA_ROLL_BUILD_ID") {
+        return id;
+    }
+
+    Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|id| id.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+// Reruns the script when the build id could change: the env override, a new
+// checkout or a new commit on the checked out branch, and the contract source
+fn rerun_directives() {
+    println!("cargo:rerun-if-env-changed=PANDA_ROLL_BUILD_ID");
+    println!("cargo:rerun-if-changed=src");
+
+    let git_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join(".git");
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+
+    if let Some(head_ref) = fs::read_to_string(git_dir.join("HEAD"))
+        .ok()
+        .and_then(|head| head.strip_prefix("ref:").map(|head_ref| head_ref.trim().to_owned()))
+    {
+        println!("cargo:rerun-if-changed={}", git_dir.join(head_ref).display());
+    }
+}
+
 fn main() {
+    // Emitted before the recursion guard so the nested wasm build gets them too
+    rerun_directives();
+    println!("cargo:rustc-env=PANDA_ROLL_BUILD_ID={}", build_id());
+
     if std::env::var("BUILD_IN_PROGRESS").is_ok() {
         println!("Build script already running, skipping to prevent recursion");
         return;
@@ -0,0 +1,143 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+
+use crate::{alkane_id_to_bytes, PandaRoll, RollOutcome};
+
+enum Milestone {
+  Rolls(u128),
+  Wins(u128),
+  LossStreak(u128),
+}
+
+// Achievement ids index into this table, so new entries only go on the end
+const ACHIEVEMENTS: &[Milestone] = &[
+  // First roll
+  Milestone::Rolls(1),
+  // 10 wins
+  Milestone::Wins(10),
+  // 100 rolls
+  Milestone::Rolls(100),
+  // Survived a 10-loss streak
+  Milestone::LossStreak(10),
+];
+
+impl PandaRoll {
+  // Badges are deployed from this template, initialised with the achievement id
+  pub(crate) fn set_badge_template(&self, tx: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.badge_template_pointer().set_value::<u128>(tx);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn claim_achievement(&self, id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let milestone = usize::try_from(id).ok()
+      .and_then(|index| ACHIEVEMENTS.get(index))
+      .ok_or_else(|| anyhow!("Unknown achievement"))?;
+
+    let template = self.badge_template_pointer().get_value::<u128>();
+    if template == 0 {
+      return Err(anyhow!("Badges are not available"));
+    }
+
+    let stats = self.roll_stats_pointer(&context.caller);
+    let reached = match milestone {
+      Milestone::Rolls(target) => stats.keyword("/rolls").get_value::<u128>() >= *target,
+      Milestone::Wins(target) => stats.keyword("/wins").get_value::<u128>() >= *target,
+      Milestone::LossStreak(target) => stats.keyword("/longest-loss-streak").get_value::<u128>() >= *target,
+    };
+
+    if !reached {
+      return Err(anyhow!("Achievement not reached"));
+    }
+
+    let mut claimed = stats.keyword("/claimed/").select(&id.to_le_bytes().to_vec());
+    if claimed.get_value::<u8>() != 0 {
+      return Err(anyhow!("Achievement already claimed"));
+    }
+    claimed.set_value::<u8>(1);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.alkanes.0.extend(self.spawn_from_template(template, vec![0, id])?.0);
+
+    Ok(response)
+  }
+
+  // `loss_streak` is the caller's streak including this roll
+  pub(crate) fn record_roll_stats(&self, caller: &AlkaneId, outcome: &RollOutcome, loss_streak: u128) {
+    let stats = self.roll_stats_pointer(caller);
+
+    let rolls = stats.keyword("/rolls").get_value::<u128>();
+    stats.keyword("/rolls").set_value::<u128>(rolls.saturating_add(1));
+
+    match outcome {
+      RollOutcome::Win(_) => {
+        let wins = stats.keyword("/wins").get_value::<u128>();
+        stats.keyword("/wins").set_value::<u128>(wins.saturating_add(1));
+      }
+      RollOutcome::Lose => {
+        let losses = stats.keyword("/losses").get_value::<u128>();
+        stats.keyword("/losses").set_value::<u128>(losses.saturating_add(1));
+      }
+      RollOutcome::Push => {}
+    }
+
+    if loss_streak > stats.keyword("/longest-loss-streak").get_value::<u128>() {
+      stats.keyword("/longest-loss-streak").set_value::<u128>(loss_streak);
+    }
+  }
+
+  // Pandas a caller has won as prizes and lost as stakes, for their net result
+  pub(crate) fn add_player_pandas(&self, caller: &AlkaneId, won: u128, lost: u128) -> Result<()> {
+    let stats = self.roll_stats_pointer(caller);
+
+    let total_won = stats.keyword("/pandas-won").get_value::<u128>();
+    stats.keyword("/pandas-won").set_value::<u128>(total_won.saturating_add(won));
+
+    let total_lost = stats.keyword("/pandas-lost").get_value::<u128>();
+    stats.keyword("/pandas-lost").set_value::<u128>(total_lost.saturating_add(lost));
+
+    self.update_leaderboard(caller)
+  }
+
+  pub(crate) fn net_pandas(&self, caller: &AlkaneId) -> i128 {
+    let stats = self.roll_stats_pointer(caller);
+    let won = stats.keyword("/pandas-won").get_value::<u128>();
+    let lost = stats.keyword("/pandas-lost").get_value::<u128>();
+
+    (won.min(i128::MAX as u128) as i128).saturating_sub(lost.min(i128::MAX as u128) as i128)
+  }
+
+  // Rolls, wins, losses, pandas won and pandas lost for any player, as u128s
+  pub(crate) fn get_player_stats(&self, block: u128, tx: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let stats = self.roll_stats_pointer(&AlkaneId { block, tx });
+    let mut data = Vec::new();
+    for stat in ["/rolls", "/wins", "/losses", "/pandas-won", "/pandas-lost"] {
+      data.extend_from_slice(&stats.keyword(stat).get_value::<u128>().to_le_bytes());
+    }
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  fn roll_stats_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/roll-stats/").select(&alkane_id_to_bytes(caller))
+  }
+
+  pub(crate) fn badge_template_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/badge-template")
+  }
+}
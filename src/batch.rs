@@ -0,0 +1,53 @@
+use alkanes_runtime::{message::MessageDispatch, runtime::AlkaneResponder};
+use alkanes_support::response::CallResponse;
+
+use anyhow::{anyhow, Result};
+
+use crate::{PandaRoll, PandaRollMessage};
+
+// Most queries one BatchQuery may run
+const MAX_BATCH_QUERIES: u128 = 16;
+
+const BATCH_QUERY_OPCODE: u128 = 138;
+
+// Opcodes a batch may run: the views from GetName up, below the two-phase roll
+// actions at 150, and the ABI. Batches don't nest.
+fn is_view_opcode(opcode: u128) -> bool {
+  ((99..150).contains(&opcode) && opcode != BATCH_QUERY_OPCODE) || opcode == 999
+}
+
+impl PandaRoll {
+  // Runs `count` view queries in one call. Each follows in the call inputs as its
+  // opcode, how many arguments it takes, then those arguments. Results come back
+  // in the same order, each as a u128 byte length followed by the view's data.
+  pub(crate) fn batch_query(&self, count: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    if count > MAX_BATCH_QUERIES {
+      return Err(anyhow!("At most {} queries per batch", MAX_BATCH_QUERIES));
+    }
+
+    // inputs[0] is the opcode and inputs[1] the count
+    let mut inputs = context.inputs.iter().skip(2).copied();
+    let mut next = || inputs.next().ok_or_else(|| anyhow!("Batch query is truncated"));
+
+    let mut data = Vec::new();
+    for _ in 0..count {
+      let opcode = next()?;
+      if !is_view_opcode(opcode) {
+        return Err(anyhow!("Opcode {} is not a view", opcode));
+      }
+
+      let arguments = (0..next()?).map(|_| next()).collect::<Result<Vec<u128>>>()?;
+      let result = PandaRollMessage::from_opcode(opcode, arguments)?.dispatch(self)?;
+
+      data.extend_from_slice(&(result.data.len() as u128).to_le_bytes());
+      data.extend(result.data);
+    }
+
+    response.data = data;
+
+    Ok(response)
+  }
+}
@@ -0,0 +1,147 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, PandaRoll};
+
+// Blocks an open challenge waits for an opponent before it can be cancelled
+const CHALLENGE_EXPIRY_BLOCKS: u64 = 144;
+
+// Entropy values below this go to the challenge creator
+const CREATOR_WIN_THRESHOLD: u8 = 128;
+
+const STATE_OPEN: u8 = 1;
+// The creator won and has yet to collect both pandas
+const STATE_CREATOR_WON: u8 = 2;
+const STATE_CLOSED: u8 = 3;
+
+impl PandaRoll {
+  pub(crate) fn create_challenge(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let panda = self.single_panda(&context.incoming_alkanes.0)?;
+
+    let challenge_id = self.challenges_count();
+    self.challenges_pointer().set_value::<u128>(challenge_id + 1);
+
+    let challenge = self.challenge_pointer(challenge_id);
+    challenge.keyword("/creator").set(Arc::new(alkane_id_to_bytes(&context.caller)));
+    challenge.keyword("/panda").set(Arc::new(alkane_id_to_bytes(&panda)));
+    challenge.keyword("/expiry").set_value::<u64>(self.height() + CHALLENGE_EXPIRY_BLOCKS);
+    challenge.keyword("/state").set_value::<u8>(STATE_OPEN);
+
+    let mut response = CallResponse::default();
+    response.data = challenge_id.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn accept_challenge(&self, challenge_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let panda = self.single_panda(&context.incoming_alkanes.0)?;
+    let txid = self.transaction_id()?;
+
+    let challenge = self.challenge_pointer(challenge_id);
+    if challenge.keyword("/state").get_value::<u8>() != STATE_OPEN {
+      return Err(anyhow!("Challenge is not open"));
+    }
+
+    let creator_panda = alkane_id_from_bytes(&challenge.keyword("/panda").get())?;
+
+    if self.calculate_random_value(&txid)? < CREATOR_WIN_THRESHOLD {
+      // Keep both pandas escrowed until the creator claims them
+      challenge.keyword("/opponent-panda").set(Arc::new(alkane_id_to_bytes(&panda)));
+      challenge.keyword("/state").set_value::<u8>(STATE_CREATOR_WON);
+
+      let mut response = CallResponse::default();
+      response.data = vec![0];
+
+      Ok(response)
+    } else {
+      challenge.keyword("/state").set_value::<u8>(STATE_CLOSED);
+
+      let mut response = CallResponse::forward(&context.incoming_alkanes);
+      response.alkanes.0.push(AlkaneTransfer {
+        id: creator_panda,
+        value: 1u128,
+      });
+      response.data = vec![1];
+
+      Ok(response)
+    }
+  }
+
+  pub(crate) fn cancel_challenge(&self, challenge_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let challenge = self.challenge_pointer(challenge_id);
+
+    self.only_challenge_creator(challenge_id)?;
+
+    if challenge.keyword("/state").get_value::<u8>() != STATE_OPEN {
+      return Err(anyhow!("Challenge is not open"));
+    }
+
+    if self.height() < challenge.keyword("/expiry").get_value::<u64>() {
+      return Err(anyhow!("Challenge has not expired yet"));
+    }
+
+    challenge.keyword("/state").set_value::<u8>(STATE_CLOSED);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.alkanes.0.push(AlkaneTransfer {
+      id: alkane_id_from_bytes(&challenge.keyword("/panda").get())?,
+      value: 1u128,
+    });
+
+    Ok(response)
+  }
+
+  pub(crate) fn claim_challenge(&self, challenge_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let challenge = self.challenge_pointer(challenge_id);
+
+    self.only_challenge_creator(challenge_id)?;
+
+    if challenge.keyword("/state").get_value::<u8>() != STATE_CREATOR_WON {
+      return Err(anyhow!("Nothing to claim for this challenge"));
+    }
+
+    challenge.keyword("/state").set_value::<u8>(STATE_CLOSED);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    for key in ["/panda", "/opponent-panda"] {
+      response.alkanes.0.push(AlkaneTransfer {
+        id: alkane_id_from_bytes(&challenge.keyword(key).get())?,
+        value: 1u128,
+      });
+    }
+
+    Ok(response)
+  }
+
+  fn only_challenge_creator(&self, challenge_id: u128) -> Result<()> {
+    let context = self.context()?;
+    let creator = self.challenge_pointer(challenge_id).keyword("/creator").get();
+
+    if creator.len() == 0 || alkane_id_from_bytes(&creator)? != context.caller {
+      return Err(anyhow!("Caller did not create this challenge"));
+    }
+
+    Ok(())
+  }
+
+  fn challenges_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/challenges/")
+  }
+
+  fn challenges_count(&self) -> u128 {
+    self.challenges_pointer().get_value::<u128>()
+  }
+
+  fn challenge_pointer(&self, challenge_id: u128) -> StoragePointer {
+    self.challenges_pointer().select(&challenge_id.to_le_bytes().to_vec())
+  }
+}
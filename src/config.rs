@@ -0,0 +1,135 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::runtime::AlkaneResponder;
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, PandaRoll, PrizeTier};
+
+impl PandaRoll {
+  // Every admin-tunable parameter as a packed list of u128s, in this order:
+  //
+  //   jackpot enabled, pity threshold, parlay threshold,
+  //   common tier handicap, rare tier handicap,
+  //   insurance premium, consolation amount,
+  //   roll limit window, roll limit cap,
+  //   lucky block pattern, length and boost,
+  //   oracle block, tx and opcode,
+  //   reroll token block and tx, payment token block and tx,
+  //   mystery box price, gacha price,
+  //   panda curve base, slope and depth,
+  //   trophy template, badge template, receipt template
+  //
+  // Unset tokens and oracles read as 0:0. New parameters only go on the end.
+  pub(crate) fn get_config(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.config_words()?.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    Ok(response)
+  }
+
+  pub(crate) fn config_words(&self) -> Result<Vec<u128>> {
+    let roll_limit = self.roll_limit_pointer();
+    let lucky_block = self.lucky_block_pointer();
+    let oracle = self.oracle_pointer();
+    let curve = self.panda_curve_pointer();
+
+    let mut config = vec![
+      self.jackpot_enabled() as u128,
+      self.pity_threshold(),
+      self.parlay_threshold() as u128,
+      self.tier_handicap(PrizeTier::Common) as u128,
+      self.tier_handicap(PrizeTier::Rare) as u128,
+      self.insurance_premium(),
+      self.consolation_amount_pointer().get_value::<u128>(),
+      roll_limit.keyword("/window").get_value::<u128>(),
+      roll_limit.keyword("/cap").get_value::<u128>(),
+      lucky_block.keyword("/pattern").get_value::<u128>(),
+      lucky_block.keyword("/length").get_value::<u128>(),
+      lucky_block.keyword("/boost").get_value::<u8>() as u128,
+    ];
+
+    config.extend(id_words(&oracle.keyword("/id").get())?);
+    config.push(oracle.keyword("/opcode").get_value::<u128>());
+    config.extend(id_words(&self.reroll_token_pointer().get())?);
+    config.extend(id_words(&self.payment_token_pointer().get())?);
+
+    config.extend([
+      self.mystery_box_price_pointer().get_value::<u128>(),
+      self.gacha_price_pointer().get_value::<u128>(),
+      curve.keyword("/base").get_value::<u128>(),
+      curve.keyword("/slope").get_value::<u128>(),
+      curve.keyword("/depth").get_value::<u128>(),
+      self.trophy_template_pointer().get_value::<u128>(),
+      self.badge_template_pointer().get_value::<u128>(),
+      self.receipt_template_pointer().get_value::<u128>(),
+    ]);
+
+    Ok(config)
+  }
+
+  // Writes back every parameter from `config_words` but the parlay threshold,
+  // which it reports with its default filled in and so can't tell apart from
+  // one that was set
+  pub(crate) fn restore_config_words(&self, words: &[u128]) -> Result<()> {
+    if words.len() != self.config_words()?.len() {
+      return Err(anyhow!("Config is from a different layout"));
+    }
+
+    let small = |word: u128| u8::try_from(word).map_err(|_| anyhow!("Invalid config word"));
+    let roll_limit = self.roll_limit_pointer();
+    let lucky_block = self.lucky_block_pointer();
+    let oracle = self.oracle_pointer();
+    let curve = self.panda_curve_pointer();
+
+    self.jackpot_enabled_pointer().set_value::<u8>(small(words[0])?);
+    self.pity_threshold_pointer().set_value::<u128>(words[1]);
+    self.tier_handicap_pointer(PrizeTier::Common).set_value::<u8>(small(words[3])?);
+    self.tier_handicap_pointer(PrizeTier::Rare).set_value::<u8>(small(words[4])?);
+    self.insurance_premium_pointer().set_value::<u128>(words[5]);
+    self.consolation_amount_pointer().set_value::<u128>(words[6]);
+    roll_limit.keyword("/window").set_value::<u128>(words[7]);
+    roll_limit.keyword("/cap").set_value::<u128>(words[8]);
+    lucky_block.keyword("/pattern").set_value::<u128>(words[9]);
+    lucky_block.keyword("/length").set_value::<u128>(words[10]);
+    lucky_block.keyword("/boost").set_value::<u8>(small(words[11])?);
+    oracle.keyword("/id").set(Arc::new(id_bytes(&words[12..14])));
+    oracle.keyword("/opcode").set_value::<u128>(words[14]);
+    self.reroll_token_pointer().set(Arc::new(id_bytes(&words[15..17])));
+    self.payment_token_pointer().set(Arc::new(id_bytes(&words[17..19])));
+    self.mystery_box_price_pointer().set_value::<u128>(words[19]);
+    self.gacha_price_pointer().set_value::<u128>(words[20]);
+    curve.keyword("/base").set_value::<u128>(words[21]);
+    curve.keyword("/slope").set_value::<u128>(words[22]);
+    curve.keyword("/depth").set_value::<u128>(words[23]);
+    self.trophy_template_pointer().set_value::<u128>(words[24]);
+    self.badge_template_pointer().set_value::<u128>(words[25]);
+    self.receipt_template_pointer().set_value::<u128>(words[26]);
+
+    Ok(())
+  }
+}
+
+// The inverse of `id_words`, [0, 0] clearing the id
+fn id_bytes(words: &[u128]) -> Vec<u8> {
+  match words {
+    [0, 0] => Vec::new(),
+    [block, tx] => alkane_id_to_bytes(&AlkaneId { block: *block, tx: *tx }),
+    _ => Vec::new(),
+  }
+}
+
+// A stored alkane id as [block, tx], or [0, 0] while it is unset
+fn id_words(bytes: &[u8]) -> Result<[u128; 2]> {
+  if bytes.is_empty() {
+    return Ok([0, 0]);
+  }
+
+  let id = alkane_id_from_bytes(bytes)?;
+
+  Ok([id.block, id.tx])
+}
@@ -0,0 +1,144 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{
+  context::Context,
+  parcel::{AlkaneTransfer, AlkaneTransferParcel},
+  response::CallResponse,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{PandaRoll, PrizeTier, RollOutcome};
+
+// Roll credits are this contract's own alkane. Depositors earn them and
+// RollWithCredits spends them in place of a panda. A roll is worth 100 credits
+// so losing rolls can be consoled with a fraction of one.
+pub(crate) const CREDITS_PER_PANDA: u128 = 100;
+const ROLL_CREDIT_COST: u128 = 100;
+
+impl PandaRoll {
+  pub(crate) fn roll_with_credits(&self, risk: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+    let max_multiplier = self.max_stake_multiplier(&context, risk)?;
+
+    // Enforce one roll per transaction
+    if self.has_tx_hash(&txid) {
+      return Err(anyhow!("Transaction already used for roll"));
+    }
+
+    let stake = self.incoming_credits(&context) / ROLL_CREDIT_COST;
+    if stake < 1 {
+      return Err(anyhow!("Must send roll credits to roll"));
+    }
+
+    let remaining = self.burn_credits(&context, stake * ROLL_CREDIT_COST)?;
+
+    // Credits are not paid back, so a win is worth (multiplier - 1) common pandas each
+    self.ensure_prizes_available(PrizeTier::Common, stake * (max_multiplier - 1))?;
+
+    self.add_tx_hash(&txid)?;
+    self.count_roll(&context.caller)?;
+
+    let (_, outcome, _) = self.draw_outcome(&context, &txid, 0, risk, self.tier_handicap(PrizeTier::Common), false, false)?;
+
+    let mut response = CallResponse::forward(&remaining);
+    response.data = outcome.multiplier().to_le_bytes().to_vec();
+
+    match outcome {
+      RollOutcome::Lose => self.pay_consolation(&context, &mut response, stake)?,
+      RollOutcome::Push => {
+        // Near miss - the spent credits are minted back with a consolation
+        response.alkanes.0.push(self.mint_credits(&context, stake * ROLL_CREDIT_COST)?);
+        self.pay_consolation(&context, &mut response, stake)?;
+      }
+      RollOutcome::Win(multiplier) => {
+        let prizes_from = response.alkanes.0.len();
+        self.pay_prizes(&mut response, PrizeTier::Common, stake * (multiplier - 1))?;
+        self.log_roll_prizes(&context.caller, &txid, &response.alkanes.0[prizes_from..])?;
+      }
+    }
+
+    self.mint_receipt(&mut response, &txid, &outcome)?;
+
+    Ok(response)
+  }
+
+  // Losing rolls mint a few credits per staked panda when an amount is configured
+  pub(crate) fn pay_consolation(&self, context: &Context, response: &mut CallResponse, stake: u128) -> Result<()> {
+    let amount = self.consolation_amount().checked_mul(stake)
+      .ok_or_else(|| anyhow!("consolation amount overflow"))?;
+
+    if amount > 0 {
+      response.alkanes.0.push(self.mint_credits(context, amount)?);
+    }
+
+    Ok(())
+  }
+
+  pub(crate) fn set_consolation_amount(&self, amount: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.consolation_amount_pointer().set_value::<u128>(amount);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn get_credit_supply(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.credit_supply().to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn mint_credits(&self, context: &Context, amount: u128) -> Result<AlkaneTransfer> {
+    let supply = self.credit_supply().checked_add(amount)
+      .ok_or_else(|| anyhow!("credit supply overflow"))?;
+    self.credit_supply_pointer().set_value::<u128>(supply);
+
+    Ok(AlkaneTransfer {
+      id: context.myself,
+      value: amount,
+    })
+  }
+
+  // Takes `amount` credits out of the incoming alkanes and returns everything else
+  pub(crate) fn burn_credits(&self, context: &Context, amount: u128) -> Result<AlkaneTransferParcel> {
+    if self.incoming_credits(context) < amount {
+      return Err(anyhow!("Not enough roll credits sent"));
+    }
+
+    let remaining = self.take_incoming(context, &context.myself, amount)?;
+
+    let supply = self.credit_supply().checked_sub(amount)
+      .ok_or_else(|| anyhow!("credit supply underflow"))?;
+    self.credit_supply_pointer().set_value::<u128>(supply);
+
+    Ok(remaining)
+  }
+
+  fn incoming_credits(&self, context: &Context) -> u128 {
+    self.incoming_amount(context, &context.myself)
+  }
+
+  pub(crate) fn consolation_amount_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/consolation-amount")
+  }
+
+  fn consolation_amount(&self) -> u128 {
+    self.consolation_amount_pointer().get_value::<u128>()
+  }
+
+  fn credit_supply_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/credits/supply")
+  }
+
+  fn credit_supply(&self) -> u128 {
+    self.credit_supply_pointer().get_value::<u128>()
+  }
+}
@@ -0,0 +1,108 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::response::CallResponse;
+
+use anyhow::Result;
+use std::sync::Arc;
+
+use crate::PandaRoll;
+
+// Block hashes kept in the ring buffer of recent entropy
+const RECENT_BLOCK_HASHES: u64 = 6;
+
+// Roll values covered by the histogram, and its bucket count. Each bucket spans
+// 256 / HISTOGRAM_BUCKETS values.
+const HISTOGRAM_WINDOW: u128 = 256;
+const HISTOGRAM_BUCKETS: u8 = 16;
+
+// A ring of the last few distinct block hashes seen by the contract. It is
+// filled lazily after each roll, and anyone can top it up with UpdateEntropy.
+impl PandaRoll {
+  pub(crate) fn update_entropy(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    self.record_block_hash()?;
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // Stores the current block hash once per block
+  pub(crate) fn record_block_hash(&self) -> Result<()> {
+    let height = self.height();
+    let recent = self.recent_hashes_pointer();
+
+    let mut last_height = recent.keyword("/last-height");
+    if last_height.get_value::<u64>() == height {
+      return Ok(());
+    }
+    last_height.set_value::<u64>(height);
+
+    let mut slot = recent.keyword("/next");
+    let index = slot.get_value::<u64>();
+    recent.keyword("/hashes/").select(&index.to_le_bytes().to_vec()).set(Arc::new(self.block_hash()?));
+    slot.set_value::<u64>((index + 1) % RECENT_BLOCK_HASHES);
+
+    Ok(())
+  }
+
+  // Every stored hash, in slot order
+  pub(crate) fn recent_block_hashes(&self) -> Vec<u8> {
+    let hashes = self.recent_hashes_pointer().keyword("/hashes/");
+
+    (0..RECENT_BLOCK_HASHES)
+      .flat_map(|index| hashes.select(&index.to_le_bytes().to_vec()).get().to_vec())
+      .collect()
+  }
+
+  // Bucket counts over the last HISTOGRAM_WINDOW roll values, lowest bucket first
+  pub(crate) fn get_entropy_histogram(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let buckets = self.histogram_pointer().keyword("/buckets/");
+    let mut data = Vec::new();
+    for bucket in 0..HISTOGRAM_BUCKETS {
+      data.extend_from_slice(&buckets.select(&vec![bucket]).get_value::<u128>().to_le_bytes());
+    }
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  // Adds a roll value to the window, dropping the one it replaces from its bucket
+  pub(crate) fn record_entropy_value(&self, value: u8) {
+    let histogram = self.histogram_pointer();
+    let buckets = histogram.keyword("/buckets/");
+
+    let mut recorded = histogram.keyword("/recorded");
+    let count = recorded.get_value::<u128>();
+    let mut slot = histogram.keyword("/values/").select(&(count % HISTOGRAM_WINDOW).to_le_bytes().to_vec());
+
+    if count >= HISTOGRAM_WINDOW {
+      let mut bucket = buckets.select(&vec![bucket_of(slot.get_value::<u8>())]);
+      let size = bucket.get_value::<u128>();
+      bucket.set_value::<u128>(size.saturating_sub(1));
+    }
+
+    slot.set_value::<u8>(value);
+    recorded.set_value::<u128>(count + 1);
+
+    let mut bucket = buckets.select(&vec![bucket_of(value)]);
+    let size = bucket.get_value::<u128>();
+    bucket.set_value::<u128>(size + 1);
+  }
+
+  fn histogram_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/entropy/histogram")
+  }
+
+  fn recent_hashes_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/entropy/recent")
+  }
+}
+
+fn bucket_of(value: u8) -> u8 {
+  value / (u8::MAX / HISTOGRAM_BUCKETS + 1)
+}
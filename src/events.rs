@@ -0,0 +1,94 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{context::Context, id::AlkaneId, response::CallResponse};
+
+use anyhow::Result;
+use std::sync::Arc;
+
+use crate::{alkane_id_to_bytes, PandaRoll, RollOutcome};
+
+// Event kinds, the first byte of every record
+const EVENT_DEPOSIT: u8 = 1;
+const EVENT_ROLL: u8 = 2;
+const EVENT_WIN: u8 = 3;
+const EVENT_CONFIG: u8 = 4;
+
+// Most events a single GetEventsSince call returns
+const MAX_EVENT_PAGE: u128 = 100;
+
+// A change feed for indexers. The count lives at `/events/` and event `n` at
+// `/events/` ++ n as its kind u8, the height u64 it happened at, then a payload:
+//
+// deposit: depositor id, pandas deposited u128
+// roll:    roller id, roll sequence number u128, outcome multiplier u128
+// win:     winner id, pandas paid out u128
+// config:  the admin call's inputs, opcode first, as u128s
+impl PandaRoll {
+  // Events from `seq` on, oldest first, up to `limit` of them. Starts with the
+  // total event count u128, then each event as its sequence number u128, record
+  // length u128 and record.
+  pub(crate) fn get_events_since(&self, seq: u128, limit: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let count = self.events_pointer().get_value::<u128>();
+    let end = seq.saturating_add(limit.min(MAX_EVENT_PAGE)).min(count);
+
+    response.data = count.to_le_bytes().to_vec();
+    for sequence in seq.min(end)..end {
+      let record = self.event_pointer(sequence).get();
+      response.data.extend_from_slice(&sequence.to_le_bytes());
+      response.data.extend_from_slice(&(record.len() as u128).to_le_bytes());
+      response.data.extend_from_slice(&record);
+    }
+
+    Ok(response)
+  }
+
+  pub(crate) fn emit_deposit(&self, depositor: &AlkaneId, pandas: u128) {
+    let mut payload = alkane_id_to_bytes(depositor);
+    payload.extend_from_slice(&pandas.to_le_bytes());
+    self.append_event(EVENT_DEPOSIT, &payload);
+  }
+
+  pub(crate) fn emit_roll(&self, roller: &AlkaneId, sequence: u128, outcome: &RollOutcome) {
+    let mut payload = alkane_id_to_bytes(roller);
+    payload.extend_from_slice(&sequence.to_le_bytes());
+    payload.extend_from_slice(&outcome.multiplier().to_le_bytes());
+    self.append_event(EVENT_ROLL, &payload);
+  }
+
+  pub(crate) fn emit_win(&self, winner: &AlkaneId, pandas: u128) {
+    let mut payload = alkane_id_to_bytes(winner);
+    payload.extend_from_slice(&pandas.to_le_bytes());
+    self.append_event(EVENT_WIN, &payload);
+  }
+
+  // Records an admin setter call as it came in, so the feed needs no per-setting
+  // layout
+  pub(crate) fn emit_config_change(&self, context: &Context) {
+    let payload = context.inputs.iter().flat_map(|input| input.to_le_bytes()).collect::<Vec<u8>>();
+    self.append_event(EVENT_CONFIG, &payload);
+  }
+
+  fn append_event(&self, kind: u8, payload: &[u8]) {
+    let mut events = self.events_pointer();
+    let sequence = events.get_value::<u128>();
+
+    let mut record = vec![kind];
+    record.extend_from_slice(&self.height().to_le_bytes());
+    record.extend_from_slice(payload);
+
+    self.event_pointer(sequence).set(Arc::new(record));
+    events.set_value::<u128>(sequence + 1);
+  }
+
+  fn events_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/events/")
+  }
+
+  fn event_pointer(&self, sequence: u128) -> StoragePointer {
+    self.events_pointer().select(&sequence.to_le_bytes().to_vec())
+  }
+}
@@ -0,0 +1,281 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::{sha256, Hash};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, digest_value, round_preimage, PandaRoll, RollOutcome};
+
+// How a logged draw was scored
+pub(crate) enum Scoring {
+  Roll(OutcomeInputs),
+  // Every round up to the first under `threshold` off one seed, doubling each time
+  Parlay { rounds: u128, threshold: u8 },
+}
+
+// Where a draw's preimage starts
+#[derive(Clone)]
+pub(crate) enum DrawBase {
+  // The contract's own seed, laid out as `calculate_seed` describes
+  Seed { block_hash: Vec<u8>, recent_hashes: Vec<u8>, vout: u32, entropy_seed: Vec<u8> },
+  // A word from the configured oracle
+  Oracle(Vec<u8>),
+}
+
+impl DrawBase {
+  pub(crate) fn bytes(&self, txid: &[u8], myself: &AlkaneId) -> Vec<u8> {
+    match self {
+      DrawBase::Seed { block_hash, recent_hashes, vout, entropy_seed } => {
+        let mut seed = block_hash.clone();
+        seed.extend_from_slice(recent_hashes);
+        seed.extend_from_slice(txid);
+        seed.extend(alkane_id_to_bytes(myself));
+        seed.extend_from_slice(&vout.to_le_bytes());
+        seed.extend_from_slice(entropy_seed);
+        seed
+      }
+      DrawBase::Oracle(word) => word.clone(),
+    }
+  }
+}
+
+// What follows the base in a draw's preimage
+#[derive(Clone, Copy)]
+pub(crate) enum DrawTail {
+  // A roll's salt, the player it is for and their roll nonce
+  Roll { salt: u128, caller: AlkaneId, nonce: u128 },
+  // A numbered round off the contract's seed, as rerolls draw
+  Round(u128),
+}
+
+// Everything a draw's preimage is built from, short of the txid and this
+// contract's id
+#[derive(Clone)]
+pub(crate) struct Draw {
+  pub(crate) base: DrawBase,
+  pub(crate) tail: DrawTail,
+}
+
+impl Draw {
+  pub(crate) fn preimage(&self, txid: &[u8], myself: &AlkaneId) -> Vec<u8> {
+    let mut preimage = self.base.bytes(txid, myself);
+    match self.tail {
+      DrawTail::Roll { salt, caller, nonce } => {
+        preimage.extend_from_slice(&salt.to_le_bytes());
+        preimage.extend(alkane_id_to_bytes(&caller));
+        preimage.extend_from_slice(&nonce.to_le_bytes());
+      }
+      DrawTail::Round(round) => preimage.extend_from_slice(&round.to_le_bytes()),
+    }
+    preimage
+  }
+
+  fn store(&self, entry: &StoragePointer) {
+    match &self.base {
+      DrawBase::Seed { block_hash, recent_hashes, vout, entropy_seed } => {
+        entry.keyword("/block-hash").set(Arc::new(block_hash.clone()));
+        entry.keyword("/recent-hashes").set(Arc::new(recent_hashes.clone()));
+        entry.keyword("/vout").set_value::<u32>(*vout);
+        entry.keyword("/entropy-seed").set(Arc::new(entropy_seed.clone()));
+      }
+      DrawBase::Oracle(word) => entry.keyword("/oracle-word").set(Arc::new(word.clone())),
+    }
+
+    match self.tail {
+      DrawTail::Roll { salt, caller, nonce } => {
+        entry.keyword("/salt").set_value::<u128>(salt);
+        entry.keyword("/caller").set(Arc::new(alkane_id_to_bytes(&caller)));
+        entry.keyword("/nonce").set_value::<u128>(nonce);
+      }
+      DrawTail::Round(round) => {
+        entry.keyword("/round").set_value::<u128>(round);
+        entry.keyword("/tail").set_value::<u8>(1);
+      }
+    }
+  }
+
+  fn load(entry: &StoragePointer) -> Result<Self> {
+    let word = entry.keyword("/oracle-word").get();
+    let base = if word.len() > 0 {
+      DrawBase::Oracle(word.to_vec())
+    } else {
+      DrawBase::Seed {
+        block_hash: entry.keyword("/block-hash").get().to_vec(),
+        recent_hashes: entry.keyword("/recent-hashes").get().to_vec(),
+        vout: entry.keyword("/vout").get_value::<u32>(),
+        entropy_seed: entry.keyword("/entropy-seed").get().to_vec(),
+      }
+    };
+
+    let tail = if entry.keyword("/tail").get_value::<u8>() != 0 {
+      DrawTail::Round(entry.keyword("/round").get_value::<u128>())
+    } else {
+      DrawTail::Roll {
+        salt: entry.keyword("/salt").get_value::<u128>(),
+        caller: alkane_id_from_bytes(&entry.keyword("/caller").get())?,
+        nonce: entry.keyword("/nonce").get_value::<u128>(),
+      }
+    };
+
+    Ok(Self { base, tail })
+  }
+}
+
+// Everything besides the entropy value that decides a roll's outcome, as it
+// stood when the roll was made
+#[derive(Clone, Copy)]
+pub(crate) struct OutcomeInputs {
+  pub(crate) risk: u128,
+  pub(crate) handicap: u8,
+  // Rarity bonus plus lucky block boost
+  pub(crate) bonus: u8,
+  pub(crate) traits: u8,
+  pub(crate) promo: Option<u8>,
+  // Where TUNED_RISK's 2x band started
+  pub(crate) threshold: u8,
+}
+
+impl OutcomeInputs {
+  // Risk u128, handicap u8, bonus u8, traits u8, a promo flag u8 and the promo
+  // threshold u8, then the win band threshold u8
+  fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = self.risk.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&[
+      self.handicap,
+      self.bonus,
+      self.traits,
+      self.promo.is_some() as u8,
+      self.promo.unwrap_or(0),
+      self.threshold,
+    ]);
+    bytes
+  }
+
+  fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    if bytes.len() != 22 {
+      return Err(anyhow!("Invalid roll inputs"));
+    }
+
+    Ok(Self {
+      risk: u128::from_le_bytes(bytes[..16].try_into().unwrap()),
+      handicap: bytes[16],
+      bonus: bytes[17],
+      traits: bytes[18],
+      promo: if bytes[19] != 0 { Some(bytes[20]) } else { None },
+      threshold: bytes[21],
+    })
+  }
+}
+
+// Every roll keeps the exact preimage its entropy value was hashed from, which
+// for a reroll is the second draw, along with each part the preimage was built
+// from and the outcome inputs it was settled with. Anyone can then rebuild the
+// preimage and replay both the hash and the outcome. The proof lives in the roll's own roll log entry, next to the value
+// and outcome `append_roll` recorded, so a txid's proof is that of its latest roll.
+impl PandaRoll {
+  // `drawn` is the outcome the entropy value gave before pity. A parlay logs the
+  // round that decided it, its first losing round or else its last.
+  pub(crate) fn log_roll(&self, sequence: u128, draw: &Draw, preimage: &Vec<u8>, value: u8, scoring: &Scoring, drawn: &RollOutcome) -> Result<()> {
+    let entry = self.roll_entry_pointer(sequence);
+    entry.keyword("/preimage").set(Arc::new(preimage.clone()));
+    entry.keyword("/block-hash").set(Arc::new(self.block_hash()?));
+    draw.store(&entry);
+    match scoring {
+      Scoring::Roll(inputs) => entry.keyword("/inputs").set(Arc::new(inputs.to_bytes())),
+      Scoring::Parlay { rounds, threshold } => {
+        let mut parlay = rounds.to_le_bytes().to_vec();
+        parlay.push(*threshold);
+        entry.keyword("/parlay").set(Arc::new(parlay));
+      }
+    }
+    entry.keyword("/drawn").set_value::<u128>(drawn.multiplier());
+    self.record_entropy_value(value);
+
+    Ok(())
+  }
+
+  // Txid of the most recent roll followed by the same proof VerifyRoll returns
+  pub(crate) fn get_roll_seed(&self) -> Result<CallResponse> {
+    let key = self.rolls_count().checked_sub(1)
+      .map(|sequence| self.roll_entry_pointer(sequence).keyword("/txid").get())
+      .ok_or_else(|| anyhow!("No rolls recorded yet"))?;
+
+    let mut response = self.verify_roll(
+      u128::from_le_bytes(key[..16].try_into().unwrap()),
+      u128::from_le_bytes(key[16..].try_into().unwrap()),
+    )?;
+
+    let mut data = key.to_vec();
+    data.extend_from_slice(&response.data);
+    response.data = data;
+
+    Ok(response)
+  }
+
+  // Recorded and recomputed entropy value u8 each, 1 if the preimage rebuilt from
+  // its recorded parts and the txid matches the recorded one u8, recorded and
+  // recomputed outcome before pity u128 each, the outcome paid u128, the block
+  // hash, the salt u128, how the roll was scored, then the recorded preimage.
+  // Scoring is 0 and the outcome inputs as OutcomeInputs lays them out for a
+  // roll, or 1, the rounds u128 and the threshold u8 for a parlay. The
+  // recomputed figures come from the rebuilt preimage and the scoring alone.
+  pub(crate) fn verify_roll(&self, txid_lo: u128, txid_hi: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let mut key = txid_lo.to_le_bytes().to_vec();
+    key.extend_from_slice(&txid_hi.to_le_bytes());
+
+    let position = self.roll_by_txid_pointer(&key).get_value::<u128>();
+    let entry = position.checked_sub(1)
+      .map(|sequence| self.roll_entry_pointer(sequence))
+      .ok_or_else(|| anyhow!("No roll recorded for that txid"))?;
+
+    let preimage = entry.keyword("/preimage").get();
+    if preimage.len() == 0 {
+      return Err(anyhow!("No proof recorded for that roll"));
+    }
+
+    let draw = Draw::load(&entry)?;
+    let rebuilt = draw.preimage(&key, &context.myself);
+    let recomputed = digest_value(&sha256::Hash::hash(&rebuilt).to_byte_array());
+
+    let parlay = entry.keyword("/parlay").get();
+    let (scoring, outcome) = if parlay.len() == 17 {
+      let rounds = u128::from_le_bytes(parlay[..16].try_into().unwrap());
+      let seed = draw.base.bytes(&key, &context.myself);
+      let won = (0..rounds)
+        .all(|round| digest_value(&sha256::Hash::hash(&round_preimage(&seed, round)).to_byte_array()) >= parlay[16]);
+
+      let mut scoring = vec![1u8];
+      scoring.extend_from_slice(&parlay);
+      (scoring, if won { RollOutcome::Win(1u128 << rounds) } else { RollOutcome::Lose })
+    } else {
+      let inputs = entry.keyword("/inputs").get();
+
+      let mut scoring = vec![0u8];
+      scoring.extend_from_slice(&inputs);
+      (scoring, self.outcome_for(&OutcomeInputs::from_bytes(&inputs)?, recomputed)?)
+    };
+
+    let mut data = vec![
+      entry.keyword("/value").get_value::<u8>(),
+      recomputed,
+      (rebuilt.as_slice() == preimage.as_slice()) as u8,
+    ];
+    data.extend_from_slice(&entry.keyword("/drawn").get_value::<u128>().to_le_bytes());
+    data.extend_from_slice(&outcome.multiplier().to_le_bytes());
+    data.extend_from_slice(&entry.keyword("/outcome").get_value::<u128>().to_le_bytes());
+    data.extend_from_slice(&entry.keyword("/block-hash").get());
+    data.extend_from_slice(&entry.keyword("/salt").get_value::<u128>().to_le_bytes());
+    data.extend_from_slice(&scoring);
+    data.extend_from_slice(&preimage);
+
+    response.data = data;
+
+    Ok(response)
+  }
+}
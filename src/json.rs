@@ -0,0 +1,141 @@
+use alkanes_runtime::runtime::AlkaneResponder;
+use alkanes_support::response::CallResponse;
+
+use anyhow::Result;
+#[cfg(not(feature = "json-views"))]
+use anyhow::anyhow;
+
+use crate::PandaRoll;
+#[cfg(feature = "json-views")]
+use crate::PrizeTier;
+
+// Entries per chunk of the JSON stack export
+#[cfg(feature = "json-views")]
+const JSON_EXPORT_CHUNK: u128 = 200;
+
+// The JSON views only exist with the `json-views` feature. Without it their
+// opcodes stay put but fail.
+#[cfg(feature = "json-views")]
+impl PandaRoll {
+  pub(crate) fn get_panda_stack_json(&self, tier: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let pool = self.pool(PrizeTier::from_u128(tier)?);
+    let count = pool.count();
+    let mut panda_ids = Vec::new();
+
+    for i in 0..count {
+      let instance_id = pool.get(i)?;
+      panda_ids.push(string(&format!("{}:{}", instance_id.block, instance_id.tx)));
+    }
+
+    response.data = array(&panda_ids).into_bytes();
+    Ok(response)
+  }
+
+  // One chunk of the pool as {"pandas": [...], "cursor": next}. Start from cursor 0
+  // and pass back each returned cursor until it comes back null.
+  pub(crate) fn export_panda_stack_json(&self, tier: u128, cursor: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let pool = self.pool(PrizeTier::from_u128(tier)?);
+    let count = pool.count();
+    let end = cursor.saturating_add(JSON_EXPORT_CHUNK).min(count);
+
+    let mut panda_ids = Vec::new();
+    for i in cursor.min(end)..end {
+      let instance_id = pool.get(i)?;
+      panda_ids.push(string(&format!("{}:{}", instance_id.block, instance_id.tx)));
+    }
+
+    // Cursors go out as strings, since JSON numbers can't hold every u128
+    let next = if end < count { string(&end.to_string()) } else { String::from("null") };
+
+    response.data = format!("{{\"pandas\":{},\"cursor\":{}}}", array(&panda_ids), next).into_bytes();
+    Ok(response)
+  }
+
+  // [{"player": "block:tx", "net": pandas won less pandas lost}, ...], best first
+  pub(crate) fn get_leaderboard(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let entries = self.leaderboard()?.iter()
+      .map(|player| format!(
+        "{{\"player\":{},\"net\":{}}}",
+        string(&format!("{}:{}", player.block, player.tx)),
+        self.net_pandas(player),
+      ))
+      .collect::<Vec<String>>();
+
+    response.data = array(&entries).into_bytes();
+    Ok(response)
+  }
+}
+
+// Just enough JSON for the views: quoted strings and arrays of encoded values
+#[cfg(feature = "json-views")]
+fn string(value: &str) -> String {
+  let mut encoded = String::with_capacity(value.len() + 2);
+  encoded.push('"');
+
+  for c in value.chars() {
+    match c {
+      '"' => encoded.push_str("\\\""),
+      '\\' => encoded.push_str("\\\\"),
+      c if (c as u32) < 0x20 => encoded.push_str(&format!("\\u{:04x}", c as u32)),
+      c => encoded.push(c),
+    }
+  }
+
+  encoded.push('"');
+  encoded
+}
+
+#[cfg(feature = "json-views")]
+fn array(values: &[String]) -> String {
+  format!("[{}]", values.join(","))
+}
+
+#[cfg(not(feature = "json-views"))]
+impl PandaRoll {
+  pub(crate) fn get_panda_stack_json(&self, _tier: u128) -> Result<CallResponse> {
+    Err(anyhow!("JSON views are not enabled in this build"))
+  }
+
+  pub(crate) fn export_panda_stack_json(&self, _tier: u128, _cursor: u128) -> Result<CallResponse> {
+    Err(anyhow!("JSON views are not enabled in this build"))
+  }
+
+  pub(crate) fn get_leaderboard(&self) -> Result<CallResponse> {
+    Err(anyhow!("JSON views are not enabled in this build"))
+  }
+}
+
+#[cfg(all(test, feature = "json-views"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strings_are_quoted() {
+    assert_eq!(string(""), "\"\"");
+    assert_eq!(string("2:895"), "\"2:895\"");
+    assert_eq!(string("panda 🐼"), "\"panda 🐼\"");
+  }
+
+  #[test]
+  fn strings_escape_quotes_backslashes_and_control_characters() {
+    assert_eq!(string("a\"b"), "\"a\\\"b\"");
+    assert_eq!(string("a\\b"), "\"a\\\\b\"");
+    assert_eq!(string("a\nb\u{1f}"), "\"a\\u000ab\\u001f\"");
+  }
+
+  #[test]
+  fn arrays_join_encoded_values() {
+    assert_eq!(array(&[]), "[]");
+    assert_eq!(array(&[string("2:1")]), "[\"2:1\"]");
+    assert_eq!(array(&[string("2:1"), array(&[string("x")])]), "[\"2:1\",[\"x\"]]");
+  }
+}
@@ -0,0 +1,43 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::storage::StoragePointer;
+use alkanes_support::id::AlkaneId;
+
+use anyhow::Result;
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, PandaRoll};
+
+// Players kept on the leaderboard
+const LEADERBOARD_SIZE: usize = 20;
+
+// The top players by net pandas won, best first, kept as one packed list of ids
+// so an update is a single read and write. A player's place is only revisited
+// when they play, so someone who slips down may sit above players who have since
+// overtaken them until either rolls again.
+impl PandaRoll {
+  pub(crate) fn update_leaderboard(&self, player: &AlkaneId) -> Result<()> {
+    let mut players = self.leaderboard()?;
+    players.retain(|entry| entry != player);
+
+    let net = self.net_pandas(player);
+    let place = players.iter().position(|entry| self.net_pandas(entry) < net).unwrap_or(players.len());
+    if place < LEADERBOARD_SIZE {
+      players.insert(place, *player);
+      players.truncate(LEADERBOARD_SIZE);
+    }
+
+    let packed = players.iter().flat_map(alkane_id_to_bytes).collect::<Vec<u8>>();
+    self.leaderboard_pointer().set(Arc::new(packed));
+
+    Ok(())
+  }
+
+  pub(crate) fn leaderboard(&self) -> Result<Vec<AlkaneId>> {
+    self.leaderboard_pointer().get().chunks(32).map(alkane_id_from_bytes).collect()
+  }
+
+  fn leaderboard_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/leaderboard")
+  }
+}
@@ -1,267 +1,1791 @@
 use metashrew_support::index_pointer::KeyValuePointer;
 use metashrew_support::compat::to_arraybuffer_layout;
-use metashrew_support::block::AuxpowBlock;
 use metashrew_support::utils::consensus_decode;
 
 use alkanes_runtime::{
-  declare_alkane, message::MessageDispatch, storage::StoragePointer, token::Token,
-  runtime::AlkaneResponder
+  auth::AuthenticatedResponder, declare_alkane, message::MessageDispatch,
+  storage::StoragePointer, token::Token, runtime::AlkaneResponder
 };
 
 use alkanes_support::{
-  id::AlkaneId,
-  parcel::AlkaneTransfer, response::CallResponse
+  context::Context, id::AlkaneId,
+  parcel::{AlkaneTransfer, AlkaneTransferParcel}, response::CallResponse
 };
 
-use bitcoin::hashes::Hash;
-use bitcoin::{Txid, Block, Transaction};
+use bitcoin::hashes::{sha256, sha256d, Hash};
+use bitcoin::{Txid, Transaction};
 
 use anyhow::{anyhow, Result};
 use std::sync::Arc;
-use std::io::Cursor;
+use std::cell::OnceCell;
 
 // We could validate pandas ids against the collection contract 2:614, but we cbf. Save fuel.
 mod panda_ids;
-use panda_ids::PANDA_IDS;
+mod valid_ids;
+
+mod stack;
+use stack::PandaStack;
+
+mod challenge;
+mod raffle;
+mod tournament;
+
+mod credits;
+use credits::CREDITS_PER_PANDA;
+
+mod shop;
+mod practice;
+mod rarity;
+use rarity::{prize_weight, TRAIT_GOLDEN};
+
+mod lucky;
+mod promo;
+mod limits;
+mod trophy;
+mod achievements;
+mod reroll;
+use reroll::REROLL_ROUND;
+
+mod team;
+mod market;
+mod pending;
+mod side_bets;
+mod vault;
+mod reveal;
+mod fairness;
+use fairness::{Draw, DrawBase, DrawTail, OutcomeInputs, Scoring};
+mod oracle;
+mod shuffle;
+mod entropy;
+mod roll_log;
+mod json;
+mod schema;
+mod snapshot;
+mod stats;
+mod leaderboard;
+mod config;
+mod batch;
+mod events;
+mod receipt;
 
 const PANDA_BLOCK: u128 = 0x2;
 
+// The serialized header leads the block, ahead of any auxpow data
+const BLOCK_HEADER_SIZE: usize = 80;
+
+// Payout bands per risk level as (minimum entropy byte, multiplier), highest band first.
+// Anything below the last band loses.
+const RISK_LEVELS: &[&[(u8, u128)]] = &[
+  &[(254, 10), (246, 3), (155, 2)], // tiered: ~35.5% 2x, ~3% 3x, ~0.8% 10x by default
+  &[(192, 4)],                      // 25% for 4x
+  &[(230, 10)],                     // ~10% for 10x
+];
+
+// Entropy value that also pays out the whole jackpot. It sits in the top band of
+// every risk level, so a jackpot hit is always a win too.
+const JACKPOT_VALUE: u8 = 255;
+
+// Entropy values this far below a risk level's lowest win threshold are a near
+// miss, e.g. 130-140 against a threshold of 141
+const NEAR_MISS_WIDTH: u8 = 11;
+
+const WIN_STREAK_BONUS_EVERY: u128 = 3;
+
+// Pity wins pay the lowest band of this risk level, the 2x
+const PITY_RISK: u128 = 0;
+
+// Risk level whose lowest band, the 2x, starts at the configured threshold
+const TUNED_RISK: u128 = 0;
+
+// Pandas of this rarity tier and above live in, and win from, the rare pool
+const RARE_POOL_RARITY: u8 = 2;
+
+// Most entries a single stack page returns, to stay within fuel
+const MAX_STACK_PAGE: u128 = 500;
+
+// Parlay rounds are plain coin flips at the original 2x odds
+const PARLAY_THRESHOLD: u8 = 141;
+const MAX_PARLAY_ROUNDS: u128 = 5;
+
+pub(crate) fn alkane_id_to_bytes(id: &AlkaneId) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(32);
+  bytes.extend_from_slice(&id.block.to_le_bytes());
+  bytes.extend_from_slice(&id.tx.to_le_bytes());
+  bytes
+}
+
+pub(crate) fn alkane_id_from_bytes(bytes: &[u8]) -> Result<AlkaneId> {
+  if bytes.len() != 32 {
+    return Err(anyhow!("Invalid alkane id length"));
+  }
+
+  let block = u128::from_le_bytes(bytes[..16].try_into().unwrap());
+  let tx = u128::from_le_bytes(bytes[16..].try_into().unwrap());
+
+  Ok(AlkaneId { block, tx })
+}
+
+// Index below `count` drawn from the seed, one independent draw per round
+pub(crate) fn seeded_index(seed: &[u8], round: u128, count: u128) -> u128 {
+  let digest = sha256::Hash::hash(&round_preimage(seed, round)).to_byte_array();
+  u128::from_le_bytes(digest[..16].try_into().unwrap()) % count
+}
+
+// The seed followed by the round, hashed for each independent draw from a seed
+fn round_preimage(seed: &[u8], round: u128) -> Vec<u8> {
+  let mut preimage = seed.to_vec();
+  preimage.extend_from_slice(&round.to_le_bytes());
+  preimage
+}
+
+// Folds every byte of a digest into the 0-255 entropy value rolls are scored on
+pub(crate) fn digest_value(digest: &[u8; 32]) -> u8 {
+  digest.iter().fold(0u8, |value, byte| value ^ byte)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RollOutcome {
+  Lose,
+  // Near miss - the stake is returned with a consolation
+  Push,
+  Win(u128),
+}
+
+impl RollOutcome {
+  // Encoded in roll responses: 0 for a loss, 1 for a push, the payout multiplier for a win
+  fn multiplier(&self) -> u128 {
+    match self {
+      RollOutcome::Lose => 0,
+      RollOutcome::Push => 1,
+      RollOutcome::Win(multiplier) => *multiplier,
+    }
+  }
+}
+
+// What a settled Roll reports back: the outcome multiplier u128 as above, the
+// entropy value u8 it was scored on, then the prize pandas won as a u128 count
+// followed by packed 32 byte ids
+fn roll_result(outcome: &RollOutcome, value: u8, prizes: &[AlkaneId]) -> Vec<u8> {
+  let mut data = outcome.multiplier().to_le_bytes().to_vec();
+  data.push(value);
+  data.extend_from_slice(&(prizes.len() as u128).to_le_bytes());
+  for prize in prizes {
+    data.extend_from_slice(&alkane_id_to_bytes(prize));
+  }
+  data
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PrizeTier {
+  Common,
+  Rare,
+}
+
+impl PrizeTier {
+  fn from_u128(tier: u128) -> Result<Self> {
+    match tier {
+      0 => Ok(PrizeTier::Common),
+      1 => Ok(PrizeTier::Rare),
+      _ => Err(anyhow!("Invalid prize tier")),
+    }
+  }
+}
+
+// Each message runs on a fresh instance, so these only ever cache values
+// from the current transaction and block
 #[derive(Default)]
-pub struct PandaRoll(());
+pub struct PandaRoll {
+  txid: OnceCell<Txid>,
+  block_hash: OnceCell<Vec<u8>>,
+}
+
+impl AlkaneResponder for PandaRoll {}
+
+impl AuthenticatedResponder for PandaRoll {}
+
+#[derive(MessageDispatch)]
+enum PandaRollMessage {
+  #[opcode(0)]
+  Initialize,
+
+  #[opcode(42)]
+  Deposit,
+
+  #[opcode(43)]
+  Withdraw { block: u128, tx: u128 },
+
+  #[opcode(69)]
+  #[returns(Vec<u8>)]
+  Roll { risk: u128, prize_tier: u128, salt: u128 },
+
+  #[opcode(70)]
+  Parlay { rounds: u128 },
+
+  #[opcode(71)]
+  #[returns(u128)]
+  CreateChallenge,
+
+  #[opcode(72)]
+  AcceptChallenge { challenge_id: u128 },
+
+  #[opcode(73)]
+  CancelChallenge { challenge_id: u128 },
+
+  #[opcode(74)]
+  ClaimChallenge { challenge_id: u128 },
+
+  #[opcode(75)]
+  DrawRaffle,
+
+  #[opcode(76)]
+  ClaimRafflePrizes,
+
+  #[opcode(77)]
+  #[returns(u128)]
+  OpenTournament { size: u128 },
+
+  #[opcode(78)]
+  #[returns(u128)]
+  EnterTournament { tournament_id: u128 },
+
+  #[opcode(79)]
+  ResolveTournamentRound { tournament_id: u128 },
+
+  #[opcode(80)]
+  ClaimTournament { tournament_id: u128 },
+
+  #[opcode(81)]
+  RollWithCredits { risk: u128 },
+
+  #[opcode(82)]
+  InsuredRoll { risk: u128 },
+
+  #[opcode(83)]
+  BuyMysteryBox,
+
+  #[opcode(84)]
+  BuyPanda,
+
+  #[opcode(85)]
+  SellPanda,
+
+  #[opcode(86)]
+  FundTreasury,
+
+  #[opcode(87)]
+  PracticeRoll { risk: u128 },
+
+  #[opcode(88)]
+  ClaimAchievement { id: u128 },
+
+  #[opcode(89)]
+  FundRerolls,
+
+  #[opcode(90)]
+  #[returns(u128)]
+  CreateTeamBattle,
+
+  #[opcode(91)]
+  JoinTeam { battle_id: u128, team: u128 },
+
+  #[opcode(92)]
+  ResolveTeamBattle { battle_id: u128 },
+
+  #[opcode(93)]
+  ClaimTeamBattle { battle_id: u128 },
+
+  #[opcode(94)]
+  #[returns(u128)]
+  CreateMarket { target: u128, height: u128 },
+
+  #[opcode(95)]
+  TakePosition { market_id: u128, side: u128 },
+
+  #[opcode(96)]
+  SettleMarket { market_id: u128 },
+
+  #[opcode(97)]
+  ClaimMarket { market_id: u128 },
+
+  #[opcode(98)]
+  #[returns(u8)]
+  GachaPull,
+
+  #[opcode(150)]
+  #[returns(u128)]
+  ProcessQueue,
+
+  #[opcode(151)]
+  ClaimWinnings { txid_lo: u128, txid_hi: u128 },
+
+  #[opcode(152)]
+  CommitRoll { risk: u128, prize_tier: u128 },
+
+  #[opcode(153)]
+  #[returns(u128)]
+  ResolveRoll { txid_lo: u128, txid_hi: u128 },
+
+  #[opcode(154)]
+  RefundCommit { txid_lo: u128, txid_hi: u128 },
+
+  #[opcode(155)]
+  #[returns(u128)]
+  DepositToVault,
+
+  #[opcode(156)]
+  WithdrawFromVault { panda_index: u128 },
+
+  #[opcode(157)]
+  TransferInternal { to_block: u128, to_tx: u128, panda_index: u128 },
+
+  #[opcode(158)]
+  RollFromVault { risk: u128, panda_index: u128 },
+
+  #[opcode(159)]
+  CommitSeed { hash_lo: u128, hash_hi: u128 },
+
+  #[opcode(160)]
+  RevealAndRoll { preimage: u128, risk: u128, prize_tier: u128 },
+
+  #[opcode(161)]
+  #[returns(u8)]
+  ShuffleStack,
+
+  #[opcode(162)]
+  UpdateEntropy,
+
+  #[opcode(163)]
+  ReclaimTournamentEntry { tournament_id: u128, entrant: u128 },
+
+  #[opcode(164)]
+  PlaceSideBet { txid_lo: u128, txid_hi: u128, side: u128 },
+
+  #[opcode(165)]
+  ClaimSideBet { txid_lo: u128, txid_hi: u128 },
+
+  #[opcode(99)]
+  #[returns(String)]
+  GetName,
+
+  #[opcode(100)]
+  #[returns(String)]
+  GetSymbol,
+
+  #[opcode(101)]
+  #[returns(u128)]
+  GetPandaStackCount { tier: u128 },
+
+  #[opcode(102)]
+  #[returns(Vec<Vec<u8>>)]
+  GetPandaStack { tier: u128 },
+
+  #[opcode(103)]
+  #[returns(String)]
+  GetPandaStackJson { tier: u128 },
+
+  #[opcode(104)]
+  #[returns(u128)]
+  GetJackpotSize,
+
+  #[opcode(105)]
+  #[returns(u128)]
+  GetMyStreak,
+
+  #[opcode(106)]
+  #[returns(u128)]
+  GetMyTickets,
+
+  #[opcode(107)]
+  #[returns(Vec<u128>)]
+  GetRaffleInfo,
+
+  #[opcode(108)]
+  #[returns(Vec<u8>)]
+  GetTournament { tournament_id: u128 },
+
+  #[opcode(109)]
+  #[returns(u128)]
+  GetCreditSupply,
+
+  #[opcode(110)]
+  #[returns(u128)]
+  GetMyWinStreak,
+
+  #[opcode(111)]
+  #[returns(u128)]
+  GetPandaPrice,
+
+  #[opcode(112)]
+  #[returns(u128)]
+  GetSellQuote,
+
+  #[opcode(113)]
+  #[returns(Vec<u128>)]
+  GetPracticeHistory,
+
+  #[opcode(114)]
+  #[returns(u8)]
+  GetRarity { tx: u128 },
+
+  #[opcode(115)]
+  #[returns(u8)]
+  GetTraits { tx: u128 },
+
+  #[opcode(116)]
+  #[returns(u8)]
+  IsLuckyBlock,
+
+  #[opcode(117)]
+  #[returns(Vec<u128>)]
+  GetActivePromo,
+
+  #[opcode(118)]
+  #[returns(u128)]
+  GetMyRemainingRolls,
+
+  #[opcode(119)]
+  #[returns(u128)]
+  GetMyVaultCount,
+
+  #[opcode(120)]
+  #[returns(Vec<Vec<u8>>)]
+  GetMyVault,
+
+  #[opcode(121)]
+  #[returns(Vec<u8>)]
+  VerifyRoll { txid_lo: u128, txid_hi: u128 },
+
+  #[opcode(122)]
+  #[returns(Vec<u8>)]
+  GetRollSeed,
+
+  #[opcode(123)]
+  #[returns(Vec<u128>)]
+  GetEntropyHistogram,
+
+  #[opcode(124)]
+  #[returns(Vec<u8>)]
+  SimulateRoll { risk: u128, prize_tier: u128, salt: u128 },
+
+  #[opcode(125)]
+  #[returns(Vec<u8>)]
+  GetPandaStackPage { tier: u128, offset: u128, limit: u128 },
+
+  #[opcode(126)]
+  #[returns(String)]
+  ExportPandaStackJson { tier: u128, cursor: u128 },
+
+  #[opcode(127)]
+  #[returns(Vec<u8>)]
+  ExportState { page: u128 },
+
+  #[opcode(128)]
+  #[returns(Vec<u8>)]
+  ContainsPanda { block: u128, tx: u128 },
+
+  #[opcode(129)]
+  #[returns(Vec<u8>)]
+  GetPandaAtIndex { tier: u128, index: u128 },
+
+  #[opcode(130)]
+  #[returns(Vec<Vec<u8>>)]
+  GetPandaStackRange { tier: u128, start: u128, count: u128 },
+
+  #[opcode(131)]
+  #[returns(Vec<u128>)]
+  GetStats,
+
+  #[opcode(132)]
+  #[returns(Vec<u8>)]
+  GetRollByTxid { txid_lo: u128, txid_hi: u128 },
+
+  #[opcode(133)]
+  #[returns(Vec<u8>)]
+  GetLastRollResult,
+
+  #[opcode(134)]
+  #[returns(Vec<u128>)]
+  GetPlayerStats { block: u128, tx: u128 },
+
+  #[opcode(135)]
+  #[returns(String)]
+  GetLeaderboard,
+
+  #[opcode(136)]
+  #[returns(Vec<u128>)]
+  GetConfig,
+
+  #[opcode(137)]
+  #[returns(Vec<u128>)]
+  GetOdds { risk: u128, prize_tier: u128 },
+
+  #[opcode(138)]
+  #[returns(Vec<u8>)]
+  BatchQuery { count: u128 },
+
+  #[opcode(139)]
+  #[returns(Vec<u8>)]
+  GetStateRoot,
+
+  #[opcode(140)]
+  #[returns(Vec<u8>)]
+  GetStackMerkleRoot { tier: u128 },
+
+  #[opcode(141)]
+  #[returns(Vec<u8>)]
+  GetStackProof { tier: u128, index: u128 },
+
+  #[opcode(142)]
+  #[returns(Vec<u8>)]
+  GetEventsSince { seq: u128, limit: u128 },
+
+  #[opcode(143)]
+  #[returns(Vec<u8>)]
+  GetValidIds { page: u128 },
+
+  #[opcode(144)]
+  #[returns(Vec<u8>)]
+  IsValidPanda { block: u128, tx: u128 },
+
+  #[opcode(145)]
+  #[returns(Vec<u8>)]
+  GetVersion,
+
+  #[opcode(999)]
+  #[returns(String)]
+  GetAbi,
+
+  #[opcode(200)]
+  SetJackpotEnabled { enabled: u128 },
+
+  #[opcode(201)]
+  SetPityThreshold { threshold: u128 },
+
+  #[opcode(202)]
+  SetConsolationAmount { amount: u128 },
+
+  #[opcode(203)]
+  SetInsurancePremium { premium: u128 },
+
+  #[opcode(204)]
+  SetPaymentToken { block: u128, tx: u128 },
+
+  #[opcode(205)]
+  SetMysteryBoxPrice { price: u128 },
+
+  #[opcode(206)]
+  SetPandaCurve { base: u128, slope: u128, depth: u128 },
+
+  #[opcode(207)]
+  ClaimTreasury,
+
+  #[opcode(208)]
+  SetRarity { tx: u128, tier: u128 },
+
+  #[opcode(209)]
+  SetTraits { tx: u128, traits: u128 },
+
+  #[opcode(210)]
+  SetTierHandicap { tier: u128, handicap: u128 },
+
+  #[opcode(211)]
+  SetLuckyBlock { pattern: u128, length: u128, boost: u128 },
+
+  #[opcode(212)]
+  #[returns(u128)]
+  SchedulePromo { start_height: u128, end_height: u128, threshold: u128 },
+
+  #[opcode(213)]
+  SetRollLimit { window: u128, cap: u128 },
+
+  #[opcode(214)]
+  SetTrophyTemplate { tx: u128 },
+
+  #[opcode(215)]
+  SetBadgeTemplate { tx: u128 },
+
+  #[opcode(216)]
+  SetRerollToken { block: u128, tx: u128 },
+
+  #[opcode(217)]
+  SetGachaPrice { price: u128 },
+
+  #[opcode(218)]
+  SetThreshold { threshold: u128 },
+
+  #[opcode(219)]
+  SetOracle { block: u128, tx: u128, opcode: u128 },
+
+  #[opcode(220)]
+  #[returns(u128)]
+  LoadValidIds { count: u128 },
+
+  #[opcode(221)]
+  AddValidIdRange { start: u128, length: u128 },
+
+  #[opcode(222)]
+  RemoveValidIdRange { start: u128, length: u128 },
+
+  #[opcode(223)]
+  #[returns(u128)]
+  Migrate { limit: u128 },
+
+  #[opcode(224)]
+  #[returns(u128)]
+  ImportState { length: u128 },
+
+  #[opcode(225)]
+  SetReceiptTemplate { tx: u128 },
+
+  #[opcode(226)]
+  SetParlayThreshold { threshold: u128 },
+}
+
+impl Token for PandaRoll {
+  fn name(&self) -> String {
+    return String::from("Alkane Panda Roll")
+  }
+
+  fn symbol(&self) -> String {
+    return String::from("alkane-panda-roll");
+  }
+}
+
+impl PandaRoll {
+  fn initialize(&self) -> Result<CallResponse> {
+    self.observe_initialization()?;
+    let context = self.context()?;
+
+    self.set_schema_version(schema::SCHEMA_VERSION);
+    self.seed_valid_ids()?;
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    // The auth token gates the admin opcodes
+    response.alkanes.0.push(self.deploy_auth_token(1u128)?);
+
+    Ok(response)
+  }
+
+  fn set_jackpot_enabled(&self, enabled: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.jackpot_enabled_pointer().set_value::<u8>(if enabled == 0 { 0 } else { 1 });
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  fn set_pity_threshold(&self, threshold: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.pity_threshold_pointer().set_value::<u128>(threshold);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // From this entropy value a roll at TUNED_RISK wins 2x. It has to stay under
+  // the risk level's next band up.
+  fn set_threshold(&self, threshold: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    let next_band = RISK_LEVELS[TUNED_RISK as usize].iter().rev().nth(1).map_or(u8::MAX, |(start, _)| *start);
+    let threshold = u8::try_from(threshold).ok()
+      .filter(|threshold| *threshold > 0 && *threshold < next_band)
+      .ok_or_else(|| anyhow!("Invalid threshold"))?;
+    self.threshold_pointer().set_value::<u8>(threshold);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // Entropy values below this lose a parlay round
+  fn set_parlay_threshold(&self, threshold: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    let threshold = u8::try_from(threshold).ok()
+      .filter(|threshold| *threshold > 0)
+      .ok_or_else(|| anyhow!("Invalid threshold"))?;
+    self.parlay_threshold_pointer().set_value::<u8>(threshold);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // Raises every win threshold by `handicap` when a roll targets the given pool
+  fn set_tier_handicap(&self, tier: u128, handicap: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    let tier = PrizeTier::from_u128(tier)?;
+    let handicap = u8::try_from(handicap).map_err(|_| anyhow!("Invalid tier handicap"))?;
+    self.tier_handicap_pointer(tier).set_value::<u8>(handicap);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  fn get_name(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.name().into_bytes();
+
+    Ok(response)
+  }
+
+  // The JSON ABI MessageDispatch derives from this enum, the same one the
+  // runtime's __meta export serves, so it always matches the opcodes above
+  fn get_abi(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = PandaRollMessage::export_abi();
+
+    Ok(response)
+  }
+
+  // The storage schema version u128, the crate version as a u128 length and
+  // UTF-8 string, then the build id build.rs stamped in for the rest
+  fn get_version(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let version = env!("CARGO_PKG_VERSION");
+
+    response.data = self.schema_version().to_le_bytes().to_vec();
+    response.data.extend_from_slice(&(version.len() as u128).to_le_bytes());
+    response.data.extend_from_slice(version.as_bytes());
+    response.data.extend_from_slice(env!("PANDA_ROLL_BUILD_ID").as_bytes());
+
+    Ok(response)
+  }
+
+  fn get_symbol(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.symbol().into_bytes();
+
+    Ok(response)
+  }
+
+  fn accepts_panda(&self, id: &AlkaneId) -> Result<bool> {
+    Ok(id.block == PANDA_BLOCK && self.is_valid_id(id.tx)?)
+  }
+
+  fn deposit(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    // Everything is checked before anything is stored, then each pool takes its
+    // pandas in one batch
+    let mut common = Vec::new();
+    let mut rare = Vec::new();
+    for alkane in context.incoming_alkanes.0.iter() {
+      if !self.accepts_panda(&alkane.id)? {
+        return Err(anyhow!("Invalid Panda ID"));
+      }
+
+      match self.prize_tier(&alkane.id) {
+        PrizeTier::Common => common.push(alkane.id),
+        PrizeTier::Rare => rare.push(alkane.id),
+      }
+    }
+
+    self.observe_markets();
+    self.pool(PrizeTier::Common).extend(&common)?;
+    self.pool(PrizeTier::Rare).extend(&rare)?;
+
+    for alkane in context.incoming_alkanes.0.iter() {
+      self.set_depositor(&alkane.id, &context.caller);
+    }
+
+    let deposited = context.incoming_alkanes.0.len() as u128;
+    self.count_deposited(deposited);
+    self.emit_deposit(&context.caller, deposited);
+
+    let mut response = CallResponse::default();
+    response.alkanes.0.push(self.mint_credits(&context, deposited * CREDITS_PER_PANDA)?);
+
+    Ok(response)
+  }
+
+  fn withdraw(&self, block: u128, tx: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let id = AlkaneId { block, tx };
+
+    match self.depositor(&id)? {
+      Some(depositor) if depositor == context.caller => {}
+      _ => return Err(anyhow!("Panda was not deposited by caller")),
+    }
+
+    let (pool, index) = self.locate_instance(&id)?
+      .ok_or_else(|| anyhow!("Panda is not in the stack"))?;
+
+    // The credits minted for the deposit are handed back in
+    let remaining = self.burn_credits(&context, CREDITS_PER_PANDA)?;
+
+    self.observe_markets();
+    pool.remove(index)?;
+    self.clear_depositor(&id);
+
+    let mut response = CallResponse::forward(&remaining);
+    response.alkanes.0.push(AlkaneTransfer {
+      id,
+      value: 1u128,
+    });
+
+    Ok(response)
+  }
+
+  fn roll(&self, risk: u128, prize_tier: u128, salt: u128) -> Result<CallResponse> {
+    let (context, reroll) = self.take_reroll(self.context()?)?;
+    let txid = self.transaction_id()?;
+    let max_multiplier = self.max_stake_multiplier(&context, risk)?;
+    let tier = PrizeTier::from_u128(prize_tier)?;
+
+    let stake = self.validate_stake(&context, &txid)?;
+
+    self.add_tx_hash(&txid)?;
+    self.count_roll(&context.caller)?;
+
+    // A roll the chosen pool can't cover at the full payout waits in the queue
+    if self.available_prizes(tier)? < stake * (max_multiplier - 1) {
+      let mut response = self.enqueue_roll(&context, &txid, risk, tier, salt)?;
+      if reroll {
+        self.settle_reroll(&mut response, false)?;
+      }
+
+      return Ok(response);
+    }
+
+    let (value, outcome, rerolled) = self.draw_outcome(&context, &txid, salt, risk, self.tier_handicap(tier), reroll, true)?;
+
+    let mut prizes = Vec::new();
+    let mut response = match outcome {
+      RollOutcome::Lose => {
+        self.forfeit_stake(&context)?;
+
+        let mut response = CallResponse::default();
+        self.pay_consolation(&context, &mut response, stake)?;
+        self.drop_reroll(&mut response, &txid)?;
+
+        response
+      }
+      RollOutcome::Push => {
+        // Near miss - the stake comes back along with a consolation
+        let mut response = CallResponse::forward(&context.incoming_alkanes);
+        self.pay_consolation(&context, &mut response, stake)?;
+
+        response
+      }
+      RollOutcome::Win(multiplier) => {
+        let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+        // Win case - the stake comes back along with (multiplier - 1) pandas each
+        let prizes_from = response.alkanes.0.len();
+        self.pay_prizes(&mut response, tier, stake * (multiplier - 1))?;
+
+        // Every third win in a row earns a bonus panda while the common pool can cover it
+        let win_streak = self.win_streak(&context.caller);
+        if win_streak % WIN_STREAK_BONUS_EVERY == 0 && self.available_prizes(PrizeTier::Common)? > 0 {
+          self.pay_prizes(&mut response, PrizeTier::Common, 1)?;
+        }
+
+        if value == JACKPOT_VALUE {
+          let jackpot = self.jackpot().drain()?;
+          self.count_paid_out(jackpot.len() as u128);
+
+          for instance_id in jackpot {
+            response.alkanes.0.push(AlkaneTransfer {
+              id: instance_id,
+              value: 1u128,
+            });
+          }
+        }
+
+        self.log_roll_prizes(&context.caller, &txid, &response.alkanes.0[prizes_from..])?;
+        prizes.extend(response.alkanes.0[prizes_from..].iter().map(|alkane| alkane.id));
+
+        // Top band and jackpot wins come with a trophy
+        if value == JACKPOT_VALUE || multiplier >= self.max_multiplier(risk)? {
+          self.mint_trophy(&mut response, &context.caller, multiplier)?;
+        }
+
+        response
+      }
+    };
+
+    if reroll {
+      self.settle_reroll(&mut response, rerolled)?;
+    }
+
+    self.mint_receipt(&mut response, &txid, &outcome)?;
+    response.data = roll_result(&outcome, value, &prizes);
+
+    Ok(response)
+  }
+
+  // Rolls the entropy value and the resulting outcome for the caller. With `reroll`
+  // a losing first draw is replaced by a second one, and the flag reports whether it was.
+  // `at_risk` is whether a loss costs the staked pandas, see `record_outcome`.
+  #[allow(clippy::too_many_arguments)]
+  fn draw_outcome(&self, context: &Context, txid: &Txid, salt: u128, risk: u128, handicap: u8, reroll: bool, at_risk: bool) -> Result<(u8, RollOutcome, bool)> {
+    let inputs = self.outcome_inputs(context, risk, handicap)?;
+
+    let myself = self.context()?.myself;
+    let mut draw = self.roll_draw(&context.caller, salt, self.next_roll_nonce(&context.caller))?;
+    let mut preimage = draw.preimage(txid.as_byte_array(), &myself);
+    let mut value = digest_value(&sha256::Hash::hash(&preimage).to_byte_array());
+    let mut drawn = self.outcome_for(&inputs, value)?;
+
+    let rerolled = reroll && drawn == RollOutcome::Lose;
+    if rerolled {
+      draw = Draw { base: self.seed_base()?, tail: DrawTail::Round(REROLL_ROUND) };
+      preimage = draw.preimage(txid.as_byte_array(), &myself);
+      value = digest_value(&sha256::Hash::hash(&preimage).to_byte_array());
+      drawn = self.outcome_for(&inputs, value)?;
+    }
+
+    self.advance_entropy_seed(txid)?;
+
+    let outcome = self.record_outcome(context, drawn, at_risk)?;
+    let sequence = self.append_roll(context, txid, self.win_threshold(risk)?.saturating_add(handicap), value, &outcome)?;
+    self.log_roll(sequence, &draw, &preimage, value, &Scoring::Roll(inputs), &drawn)?;
+
+    Ok((value, outcome, rerolled))
+  }
+
+  // Applies pity and updates the caller's streaks and stats for a drawn outcome.
+  // Rolls that can't lose the staked pandas, insured and credit rolls, neither
+  // build nor cash in the pity streak, or cheap losses would buy a forced win.
+  fn record_outcome(&self, context: &Context, outcome: RollOutcome, at_risk: bool) -> Result<RollOutcome> {
+    let outcome = if at_risk { self.apply_pity(context, outcome)? } else { outcome };
+    let streak = self.loss_streak(&context.caller);
+
+    let streak = match outcome {
+      RollOutcome::Lose => {
+        self.set_win_streak(&context.caller, 0);
+        if at_risk {
+          self.record_streak_stake(context, streak);
+          streak.saturating_add(1)
+        } else {
+          streak
+        }
+      }
+      RollOutcome::Push => streak,
+      RollOutcome::Win(_) => {
+        self.set_win_streak(&context.caller, self.win_streak(&context.caller).saturating_add(1));
+        if at_risk { 0 } else { streak }
+      }
+    };
+    self.set_loss_streak(&context.caller, streak);
+
+    self.record_roll_stats(&context.caller, &outcome, streak);
+    self.count_settled_roll(&outcome);
+
+    Ok(outcome)
+  }
+
+  // Pity - once a caller has lost enough rolls in a row the next one wins, but
+  // only at the safest risk level's lowest band and only for a stake no bigger
+  // than the smallest one lost along the way, so a streak of cheap losses can't
+  // be cashed in on a big, high-risk roll
+  fn apply_pity(&self, context: &Context, outcome: RollOutcome) -> Result<RollOutcome> {
+    let pity_threshold = self.pity_threshold();
+    if outcome == RollOutcome::Lose
+      && pity_threshold != 0
+      && self.loss_streak(&context.caller) >= pity_threshold
+      && self.staked_pandas(context) <= self.streak_stake_pointer(&context.caller).get_value::<u128>()
+    {
+      return Ok(RollOutcome::Win(self.min_multiplier(PITY_RISK)?));
+    }
+
+    Ok(outcome)
+  }
+
+  // Keeps the smallest stake lost in the caller's current loss streak, which is
+  // `streak` losses long before this one
+  fn record_streak_stake(&self, context: &Context, streak: u128) {
+    let mut pointer = self.streak_stake_pointer(&context.caller);
+    let stake = self.staked_pandas(context);
+
+    pointer.set_value::<u128>(if streak == 0 { stake } else { stake.min(pointer.get_value::<u128>()) });
+  }
+
+  fn staked_pandas(&self, context: &Context) -> u128 {
+    context.incoming_alkanes.0.iter().filter(|alkane| alkane.id != context.myself).count() as u128
+  }
+
+  // Non-binding preview of a Roll with the same inputs in this transaction. It
+  // writes nothing, so the nonce, streaks and guards are all left as they are,
+  // and a real roll can still differ if anything changes before it lands.
+  fn simulate_roll(&self, risk: u128, prize_tier: u128, salt: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+    let tier = PrizeTier::from_u128(prize_tier)?;
+
+    let preimage = self.roll_preimage_at(&context.caller, &txid, salt, self.roll_nonce(&context.caller))?;
+    let value = digest_value(&sha256::Hash::hash(&preimage).to_byte_array());
+
+    let outcome = self.entropy_outcome(&context, value, risk, self.tier_handicap(tier))?;
+    let outcome = self.apply_pity(&context, outcome)?;
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = outcome.multiplier().to_le_bytes().to_vec();
+    response.data.push(value);
+
+    Ok(response)
+  }
+
+  // Odds of a roll made right now with the pandas sent along, if any: winning
+  // values and near-miss values out of 256, the top multiplier, the expected
+  // return per panda staked in basis points, then the house edge in basis points
+  // as an i128. Entropy values are uniform, so each of the 256 is scored as a real
+  // roll would be, the configured win threshold, promos, lucky blocks, rarity and
+  // traits included. A near miss hands the stake back, so it counts at 1x towards
+  // the expected return. Pity, jackpots and consolations are left out.
+  fn get_odds(&self, risk: u128, prize_tier: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let handicap = self.tier_handicap(PrizeTier::from_u128(prize_tier)?);
+
+    let (mut wins, mut pushes, mut returned) = (0u128, 0u128, 0u128);
+    for value in 0..=u8::MAX {
+      let outcome = self.entropy_outcome(&context, value, risk, handicap)?;
+      match outcome {
+        RollOutcome::Lose => {}
+        RollOutcome::Push => pushes += 1,
+        RollOutcome::Win(_) => wins += 1,
+      }
+      returned += outcome.multiplier();
+    }
+
+    let expected_return = returned * 10_000 / 256;
+    let house_edge = 10_000i128 - expected_return as i128;
+
+    let mut data = Vec::new();
+    for word in [wins, pushes, self.max_stake_multiplier(&context, risk)?, expected_return] {
+      data.extend_from_slice(&word.to_le_bytes());
+    }
+    data.extend_from_slice(&house_edge.to_le_bytes());
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  // Outcome of a single entropy value once every bonus and boost is applied
+  fn entropy_outcome(&self, context: &Context, value: u8, risk: u128, handicap: u8) -> Result<RollOutcome> {
+    self.outcome_for(&self.outcome_inputs(context, risk, handicap)?, value)
+  }
+
+  fn outcome_inputs(&self, context: &Context, risk: u128, handicap: u8) -> Result<OutcomeInputs> {
+    Ok(OutcomeInputs {
+      risk,
+      handicap,
+      bonus: self.rarity_bonus(context).saturating_add(self.lucky_boost()?),
+      traits: self.stake_traits(context),
+      promo: self.promo_threshold(),
+      threshold: self.win_band_threshold(),
+    })
+  }
+
+  // Reads nothing but RISK_LEVELS, so a logged roll can be replayed later
+  fn outcome_for(&self, inputs: &OutcomeInputs, value: u8) -> Result<RollOutcome> {
+    let bands = self.risk_bands(inputs.risk, inputs.threshold)?;
+    let outcome = self.calculate_outcome(value.saturating_sub(inputs.handicap), &bands, inputs.bonus);
+    let outcome = self.apply_traits(outcome, value, inputs.traits, inputs.risk)?;
+
+    // An active promo turns any value at or above its threshold into a win
+    if let Some(threshold) = inputs.promo {
+      if !matches!(outcome, RollOutcome::Win(_)) && value >= threshold {
+        return Ok(RollOutcome::Win(self.min_multiplier(inputs.risk)?));
+      }
+    }
+
+    Ok(outcome)
+  }
+
+  // Settles like a roll drawn once, so a lost parlay builds the pity streak and
+  // a pity win pays the lowest band rather than the whole parlay
+  fn parlay(&self, rounds: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+
+    if rounds < 1 || rounds > MAX_PARLAY_ROUNDS {
+      return Err(anyhow!("Invalid number of parlay rounds"));
+    }
+
+    let stake = self.validate_stake(&context, &txid)?;
+
+    // Winning every round doubles the stake each time
+    let prizes_each = (1u128 << rounds) - 1;
+    self.ensure_prizes_for_stake(&context, prizes_each)?;
+
+    self.add_tx_hash(&txid)?;
+    self.count_roll(&context.caller)?;
+
+    let base = self.seed_base()?;
+    let seed = base.bytes(txid.as_byte_array(), &context.myself);
+    self.advance_entropy_seed(&txid)?;
+
+    // Play stops at the first losing round
+    let threshold = self.parlay_threshold();
+    let mut won = 0;
+    while won < rounds && self.calculate_round_value(&seed, won) >= threshold {
+      won += 1;
+    }
+
+    // The deciding round is the one logged
+    let draw = Draw { base, tail: DrawTail::Round(won.min(rounds - 1)) };
+    let preimage = draw.preimage(txid.as_byte_array(), &context.myself);
+    let value = digest_value(&sha256::Hash::hash(&preimage).to_byte_array());
+
+    let drawn = if won == rounds { RollOutcome::Win(prizes_each + 1) } else { RollOutcome::Lose };
+    let outcome = self.record_outcome(&context, drawn, true)?;
+    let sequence = self.append_roll(&context, &txid, threshold, value, &outcome)?;
+    self.log_roll(sequence, &draw, &preimage, value, &Scoring::Parlay { rounds, threshold }, &drawn)?;
+
+    let mut response = match outcome {
+      RollOutcome::Win(multiplier) => {
+        let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+        let prizes_from = response.alkanes.0.len();
+        self.pay_prizes_for_stake(&context, &mut response, multiplier - 1)?;
+        self.log_roll_prizes(&context.caller, &txid, &response.alkanes.0[prizes_from..])?;
+
+        response
+      }
+      _ => {
+        self.forfeit_stake(&context)?;
+
+        let mut response = CallResponse::default();
+        self.pay_consolation(&context, &mut response, stake)?;
+
+        response
+      }
+    };
+    response.data = won.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Pandas come back whatever the outcome; the premium in roll credits is burned
+  fn insured_roll(&self, risk: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+    let max_multiplier = self.max_stake_multiplier(&context, risk)?;
+
+    // Enforce one roll per transaction
+    if self.has_tx_hash(&txid) {
+      return Err(anyhow!("Transaction already used for roll"));
+    }
+
+    let premium = self.insurance_premium();
+    if premium == 0 {
+      return Err(anyhow!("Insurance is not available"));
+    }
+
+    let mut stake = 0u128;
+    for alkane in context.incoming_alkanes.0.iter().filter(|alkane| alkane.id != context.myself) {
+      if !self.accepts_panda(&alkane.id)? {
+        return Err(anyhow!("Invalid Panda ID"));
+      }
+
+      stake += 1;
+    }
+
+    if stake < 1 {
+      return Err(anyhow!("Must send at least 1 Panda to roll"));
+    }
+
+    let premium_due = premium.checked_mul(stake)
+      .ok_or_else(|| anyhow!("insurance premium overflow"))?;
+    let remaining = self.burn_credits(&context, premium_due)?;
+
+    self.ensure_prizes_for_stake(&context, max_multiplier - 1)?;
+
+    self.add_tx_hash(&txid)?;
+    self.count_roll(&context.caller)?;
+
+    let (_, outcome, _) = self.draw_outcome(&context, &txid, 0, risk, 0, false, false)?;
+
+    let mut response = CallResponse::forward(&remaining);
+    response.data = outcome.multiplier().to_le_bytes().to_vec();
+
+    match outcome {
+      RollOutcome::Lose => {}
+      RollOutcome::Push => self.pay_consolation(&context, &mut response, stake)?,
+      RollOutcome::Win(multiplier) => {
+        let prizes_from = response.alkanes.0.len();
+        self.pay_prizes_for_stake(&context, &mut response, multiplier - 1)?;
+        self.log_roll_prizes(&context.caller, &txid, &response.alkanes.0[prizes_from..])?;
+      }
+    }
+
+    self.mint_receipt(&mut response, &txid, &outcome)?;
+
+    Ok(response)
+  }
+
+  fn set_insurance_premium(&self, premium: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.insurance_premium_pointer().set_value::<u128>(premium);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // Checks the pandas sent in for a roll and returns how many were staked
+  fn validate_stake(&self, context: &Context, txid: &Txid) -> Result<u128> {
+    // Enforce one roll per transaction
+    if self.has_tx_hash(txid) {
+      return Err(anyhow!("Transaction already used for roll"));
+    }
+    
+    let stake = context.incoming_alkanes.0.len() as u128;
+    if stake < 1 {
+      return Err(anyhow!("Must send at least 1 Panda to roll"));
+    }
+
+    for alkane in context.incoming_alkanes.0.iter() {
+      if !self.accepts_panda(&alkane.id)? {
+        return Err(anyhow!("Invalid Panda ID"));
+      }
+    }
+
+    Ok(stake)
+  }
+
+  fn single_panda(&self, incoming: &[AlkaneTransfer]) -> Result<AlkaneId> {
+    if incoming.len() != 1 {
+      return Err(anyhow!("Must send 1 Panda"));
+    }
+
+    if !self.accepts_panda(&incoming[0].id)? {
+      return Err(anyhow!("Invalid Panda ID"));
+    }
+
+    Ok(incoming[0].id)
+  }
+
+  fn incoming_amount(&self, context: &Context, id: &AlkaneId) -> u128 {
+    context.incoming_alkanes.0.iter()
+      .filter(|alkane| alkane.id == *id)
+      .fold(0u128, |total, alkane| total.saturating_add(alkane.value))
+  }
+
+  // Takes `amount` of `id` out of the incoming alkanes and returns everything else
+  fn take_incoming(&self, context: &Context, id: &AlkaneId, amount: u128) -> Result<AlkaneTransferParcel> {
+    if self.incoming_amount(context, id) < amount {
+      return Err(anyhow!("Insufficient payment"));
+    }
+
+    let mut outstanding = amount;
+    let mut remaining = AlkaneTransferParcel::default();
+
+    for alkane in context.incoming_alkanes.0.iter() {
+      if alkane.id == *id && outstanding > 0 {
+        let taken = outstanding.min(alkane.value);
+        outstanding -= taken;
+
+        if alkane.value > taken {
+          remaining.0.push(AlkaneTransfer {
+            id: alkane.id,
+            value: alkane.value - taken,
+          });
+        }
+      } else {
+        remaining.0.push(alkane.clone());
+      }
+    }
+
+    Ok(remaining)
+  }
+
+  fn ensure_prizes_available(&self, tier: PrizeTier, prizes: u128) -> Result<()> {
+    if self.available_prizes(tier)? < prizes {
+      return Err(anyhow!("Not enough Pandas available to roll"));
+    }
+
+    Ok(())
+  }
+
+  // Staked pandas drawing on each prize pool, as (common, rare)
+  fn stake_tiers(&self, context: &Context) -> (u128, u128) {
+    context.incoming_alkanes.0.iter()
+      .filter(|alkane| alkane.id != context.myself)
+      .fold((0u128, 0u128), |(common, rare), alkane| match self.prize_tier(&alkane.id) {
+        PrizeTier::Common => (common + 1, rare),
+        PrizeTier::Rare => (common, rare + 1),
+      })
+  }
+
+  // Every staked panda is matched by `prizes_each` pandas from its own tier's pool
+  fn ensure_prizes_for_stake(&self, context: &Context, prizes_each: u128) -> Result<()> {
+    let (common, rare) = self.stake_tiers(context);
+
+    self.ensure_prizes_available(PrizeTier::Common, common * prizes_each)?;
+    self.ensure_prizes_available(PrizeTier::Rare, rare * prizes_each)
+  }
+
+  fn pay_prizes_for_stake(&self, context: &Context, response: &mut CallResponse, prizes_each: u128) -> Result<()> {
+    let (common, rare) = self.stake_tiers(context);
+
+    self.pay_prizes(response, PrizeTier::Common, common * prizes_each)?;
+    self.pay_prizes(response, PrizeTier::Rare, rare * prizes_each)
+  }
+
+  fn forfeit_stake(&self, context: &Context) -> Result<()> {
+    // Losses feed the jackpot when it is switched on
+    let jackpot_enabled = self.jackpot_enabled();
+
+    for alkane in context.incoming_alkanes.0.iter() {  
+      if jackpot_enabled {
+        self.jackpot().push(&alkane.id)?;
+      } else {
+        self.quarantine_prize(&alkane.id)?;
+      }
+    }
+
+    self.add_player_pandas(&context.caller, 0, context.incoming_alkanes.0.len() as u128)?;
+
+    // Each lost panda is worth a raffle ticket
+    self.issue_raffle_tickets(&context.caller, context.incoming_alkanes.0.len() as u128)?;
+
+    Ok(())
+  }
+
+  // Prizes come from random positions in the pool, weighted against rarer pandas,
+  // so nobody can line up a particular panda by watching what was added last
+  fn pay_prizes(&self, response: &mut CallResponse, tier: PrizeTier, prizes: u128) -> Result<()> {
+    let pool = self.pool(tier);
+    let seed = self.calculate_seed(&self.transaction_id()?)?;
+
+    self.observe_markets();
+    self.release_quarantine(tier)?;
+    self.count_paid_out(prizes);
+
+    for prize in 0..prizes {
+      let total = pool.total_weight();
+      if total == 0 {
+        return Err(anyhow!("Not enough Pandas available"));
+      }
+
+      let instance_id = pool.remove(pool.weighted_index(seeded_index(&seed, prize, total))?)?;
+      self.clear_depositor(&instance_id);
+
+      response.alkanes.0.push(AlkaneTransfer {
+        id: instance_id,
+        value: 1u128,
+      });
+    }
+
+    Ok(())
+  }
+
+  fn risk_level(&self, risk: u128) -> Result<Vec<(u8, u128)>> {
+    self.risk_bands(risk, self.win_band_threshold())
+  }
+
+  // The risk level's bands with TUNED_RISK's lowest one starting at `threshold`
+  fn risk_bands(&self, risk: u128, threshold: u8) -> Result<Vec<(u8, u128)>> {
+    let mut bands = usize::try_from(risk).ok()
+      .and_then(|index| RISK_LEVELS.get(index))
+      .ok_or_else(|| anyhow!("Invalid risk level"))?
+      .to_vec();
+
+    if risk == TUNED_RISK {
+      if let Some(lowest) = bands.last_mut() {
+        lowest.0 = threshold;
+      }
+    }
+
+    Ok(bands)
+  }
+
+  // The configured threshold, or where RISK_LEVELS starts TUNED_RISK's 2x band
+  fn win_band_threshold(&self) -> u8 {
+    match self.threshold_pointer().get_value::<u8>() {
+      0 => RISK_LEVELS[TUNED_RISK as usize].last().map_or(u8::MAX, |(start, _)| *start),
+      threshold => threshold,
+    }
+  }
+
+  fn max_multiplier(&self, risk: u128) -> Result<u128> {
+    Ok(self.risk_level(risk)?.iter().map(|(_, multiplier)| *multiplier).max().unwrap_or(0))
+  }
+
+  // Highest multiplier the incoming stake can win, golden bonus included
+  fn max_stake_multiplier(&self, context: &Context, risk: u128) -> Result<u128> {
+    let bonus = if self.stake_traits(context) & TRAIT_GOLDEN != 0 { 1 } else { 0 };
+
+    Ok(self.max_multiplier(risk)? + bonus)
+  }
+
+  // Lowest entropy value that wins at this risk level
+  fn win_threshold(&self, risk: u128) -> Result<u8> {
+    Ok(self.risk_level(risk)?.iter().map(|(threshold, _)| *threshold).min().unwrap_or(u8::MAX))
+  }
+
+  fn min_multiplier(&self, risk: u128) -> Result<u128> {
+    Ok(self.risk_level(risk)?.iter().map(|(_, multiplier)| *multiplier).min().unwrap_or(0))
+  }
 
-impl AlkaneResponder for PandaRoll {}
+  fn calculate_random_value(&self, txid: &Txid) -> Result<u8> {
+    Ok(digest_value(&sha256::Hash::hash(&self.calculate_seed(txid)?).to_byte_array()))
+  }
 
-#[derive(MessageDispatch)]
-enum PandaRollMessage {
-  #[opcode(0)]
-  Initialize,
+  // The player's salt goes into the hash too, so they can check their own part in
+  // the outcome, along with their next roll nonce so no two of their rolls share
+  // a preimage. A configured oracle's word replaces the local seed.
+  fn roll_draw(&self, caller: &AlkaneId, salt: u128, nonce: u128) -> Result<Draw> {
+    let base = match self.oracle_word()? {
+      Some(word) => DrawBase::Oracle(word),
+      None => self.seed_base()?,
+    };
 
-  #[opcode(42)]
-  Deposit,
+    Ok(Draw { base, tail: DrawTail::Roll { salt, caller: *caller, nonce } })
+  }
 
-  #[opcode(69)]
-  Roll,
+  fn roll_preimage_at(&self, caller: &AlkaneId, txid: &Txid, salt: u128, nonce: u128) -> Result<Vec<u8>> {
+    Ok(self.roll_draw(caller, salt, nonce)?.preimage(txid.as_byte_array(), &self.context()?.myself))
+  }
 
-  #[opcode(99)]
-  #[returns(String)]
-  GetName,
+  fn next_roll_nonce(&self, caller: &AlkaneId) -> u128 {
+    let nonce = self.roll_nonce(caller);
+    self.roll_nonce_pointer(caller).set_value::<u128>(nonce + 1);
 
-  #[opcode(100)]
-  #[returns(String)]
-  GetSymbol,
+    nonce
+  }
 
-  #[opcode(101)]
-  #[returns(u128)]
-  GetPandaStackCount,
+  fn roll_nonce(&self, caller: &AlkaneId) -> u128 {
+    self.roll_nonce_pointer(caller).get_value::<u128>()
+  }
 
-  #[opcode(102)]
-  #[returns(Vec<Vec<u8>>)]
-  GetPandaStack,
+  fn roll_nonce_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/nonces/").select(&alkane_id_to_bytes(caller))
+  }
 
-  #[opcode(103)]
-  #[returns(String)]
-  GetPandaStackJson,
-}
+  // The full block hash, txid and this contract's id, so no single byte decides a
+  // roll, plus the protostone's vout so two messages in one transaction differ.
+  // The accumulated seed ties every roll to the whole history of play, and the
+  // recent block hashes mean no single block decides it.
+  fn calculate_seed(&self, txid: &Txid) -> Result<Vec<u8>> {
+    Ok(self.seed_base()?.bytes(txid.as_byte_array(), &self.context()?.myself))
+  }
 
-impl Token for PandaRoll {
-  fn name(&self) -> String {
-    return String::from("Alkane Panda Roll")
+  // The parts of `calculate_seed` that change from call to call
+  fn seed_base(&self) -> Result<DrawBase> {
+    Ok(DrawBase::Seed {
+      block_hash: self.block_hash()?,
+      recent_hashes: self.recent_block_hashes(),
+      vout: self.context()?.vout,
+      entropy_seed: self.entropy_seed_pointer().get().to_vec(),
+    })
   }
 
-  fn symbol(&self) -> String {
-    return String::from("alkane-panda-roll");
+  // seed = H(prev_seed || txid || block_hash), run after every roll
+  fn advance_entropy_seed(&self, txid: &Txid) -> Result<()> {
+    self.record_block_hash()?;
+
+    let mut preimage = self.entropy_seed_pointer().get().to_vec();
+    preimage.extend_from_slice(txid.as_byte_array());
+    preimage.extend(self.block_hash()?);
+
+    self.entropy_seed_pointer().set(Arc::new(sha256::Hash::hash(&preimage).to_byte_array().to_vec()));
+
+    Ok(())
   }
-}
 
-impl PandaRoll {
-  fn initialize(&self) -> Result<CallResponse> {
-    self.observe_initialization()?;
-    let context = self.context()?;
+  fn entropy_seed_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/seed")
+  }
 
-    let response = CallResponse::forward(&context.incoming_alkanes);
-    Ok(response)
+  fn calculate_round_value(&self, seed: &[u8], round: u128) -> u8 {
+    digest_value(&sha256::Hash::hash(&round_preimage(seed, round)).to_byte_array())
   }
 
-  fn get_name(&self) -> Result<CallResponse> {
-    let context = self.context()?;
-    let mut response = CallResponse::forward(&context.incoming_alkanes);
+  // `bonus` lifts the entropy value, which lowers every threshold by the same amount
+  fn calculate_outcome(&self, value: u8, bands: &[(u8, u128)], bonus: u8) -> RollOutcome {
+    let value = value.saturating_add(bonus);
 
-    response.data = self.name().into_bytes();
+    if let Some((_, multiplier)) = bands.iter().find(|(threshold, _)| value >= *threshold) {
+      return RollOutcome::Win(*multiplier);
+    }
 
-    Ok(response)
+    let win_threshold = bands.iter().map(|(threshold, _)| *threshold).min().unwrap_or(u8::MAX);
+
+    if value >= win_threshold.saturating_sub(NEAR_MISS_WIDTH) { RollOutcome::Push } else { RollOutcome::Lose }
   }
 
-  fn get_symbol(&self) -> Result<CallResponse> {
-    let context = self.context()?;
-    let mut response = CallResponse::forward(&context.incoming_alkanes);
+  fn pool(&self, tier: PrizeTier) -> PandaStack {
+    match tier {
+      PrizeTier::Common => PandaStack::new("/instances/common").with_merkle_tree().with_weights(prize_weight),
+      PrizeTier::Rare => PandaStack::new("/instances/rare").with_merkle_tree().with_weights(prize_weight),
+    }
+  }
 
-    response.data = self.symbol().into_bytes();
+  fn prize_tier(&self, id: &AlkaneId) -> PrizeTier {
+    if id.block == PANDA_BLOCK && self.rarity(id.tx) >= RARE_POOL_RARITY {
+      PrizeTier::Rare
+    } else {
+      PrizeTier::Common
+    }
+  }
 
-    Ok(response)
+  fn pool_for(&self, id: &AlkaneId) -> PandaStack {
+    self.pool(self.prize_tier(id))
   }
 
-  fn is_valid_panda(&self, id: &AlkaneId) -> Result<bool> {
-    Ok(id.block == PANDA_BLOCK && PANDA_IDS.contains(&id.tx))
+  // A lost panda can't be won back until the block after it was lost, so it
+  // joins its pool at weight 0 and is listed here until a later block
+  fn quarantine(&self, tier: PrizeTier) -> PandaStack {
+    PandaStack::at(&StoragePointer::from_keyword("/quarantine/").select(&vec![tier as u8]))
   }
 
-  fn deposit(&self) -> Result<CallResponse> {
-    let context = self.context()?;
+  fn quarantine_height_pointer(&self, tier: PrizeTier) -> StoragePointer {
+    StoragePointer::from_keyword("/quarantine/height/").select(&vec![tier as u8])
+  }
 
-    for alkane in context.incoming_alkanes.0.iter() {
-      if !self.is_valid_panda(&alkane.id)? {
-        return Err(anyhow!("Invalid Panda ID"));
-      }
+  fn quarantine_prize(&self, id: &AlkaneId) -> Result<()> {
+    let tier = self.prize_tier(id);
+    self.observe_markets();
+    self.release_quarantine(tier)?;
 
-      self.add_instance(&alkane.id)?;
-    }
+    let pool = self.pool(tier);
+    let count = pool.push(id)?;
+    pool.set_weight(count - 1, 0)?;
 
-    Ok(CallResponse::default())
+    self.quarantine(tier).push(id)?;
+    self.quarantine_height_pointer(tier).set_value::<u64>(self.height());
+
+    Ok(())
   }
 
-  fn roll(&self) -> Result<CallResponse> {
-    let context = self.context()?;
-    let txid = self.transaction_id()?;
+  // Pandas in the pool that can be drawn this block, leaving out the quarantined
+  fn available_prizes(&self, tier: PrizeTier) -> Result<u128> {
+    let pool = self.pool(tier);
+    let quarantine = self.quarantine(tier);
+    if self.quarantine_height_pointer(tier).get_value::<u64>() != self.height() {
+      return Ok(pool.count());
+    }
 
-    // Enforce one roll per transaction
-    if self.has_tx_hash(&txid) {
-      return Err(anyhow!("Transaction already used for roll"));
+    let mut held = 0u128;
+    for index in 0..quarantine.count() {
+      if pool.find(&quarantine.get(index)?)?.is_some() {
+        held += 1;
+      }
     }
-    
-    if context.incoming_alkanes.0.len() != 1 {
-      return Err(anyhow!("Must send 1 Panda to roll"));
+
+    Ok(pool.count() - held)
+  }
+
+  // Gives pandas quarantined in an earlier block their prize weight back
+  fn release_quarantine(&self, tier: PrizeTier) -> Result<()> {
+    let quarantine = self.quarantine(tier);
+    if quarantine.count() == 0 || self.quarantine_height_pointer(tier).get_value::<u64>() == self.height() {
+      return Ok(());
     }
 
-    if !self.is_valid_panda(&context.incoming_alkanes.0[0].id)? {
-      return Err(anyhow!("Invalid Panda ID"));
+    let pool = self.pool(tier);
+    for id in quarantine.drain()? {
+      if let Some(index) = pool.find(&id)? {
+        pool.reweigh(index)?;
+      }
     }
 
-    let count = self.instances_count();
-    if count < 1 {
-      return Err(anyhow!("Not enough Pandas available to roll"));
+    Ok(())
+  }
+
+  // Picks up a rarity change in the weight of a panda sitting in a pool, unless
+  // it is still quarantined
+  pub(crate) fn reweigh_prize(&self, id: &AlkaneId) -> Result<()> {
+    for tier in [PrizeTier::Common, PrizeTier::Rare] {
+      let pool = self.pool(tier);
+
+      if let Some(index) = pool.find(id)? {
+        self.release_quarantine(tier)?;
+        if self.quarantine(tier).find(id)?.is_none() {
+          pool.reweigh(index)?;
+        }
+      }
     }
 
-    self.add_tx_hash(&txid)?;
+    Ok(())
+  }
 
-    let multiplier = self.calculate_random_multiplier(&txid)?;
+  // Looks in both pools, since a panda's rarity may have changed since it was added
+  fn locate_instance(&self, id: &AlkaneId) -> Result<Option<(PandaStack, u128)>> {
+    for tier in [PrizeTier::Common, PrizeTier::Rare] {
+      let pool = self.pool(tier);
 
-    if multiplier == 0 {
-      for alkane in context.incoming_alkanes.0.iter() {  
-        self.add_instance(&alkane.id)?;
+      if let Some(index) = pool.find(id)? {
+        return Ok(Some((pool, index)));
       }
-  
-      Ok(CallResponse::default())
-    } else {
-      let mut response = CallResponse::forward(&context.incoming_alkanes);
+    }
 
-      // Win case - add one more panda
-      let instance_id = self.pop_instance()?;
+    Ok(None)
+  }
 
-      response.alkanes.0.push(AlkaneTransfer {
-        id: instance_id,
-        value: 1u128,
-      }); 
+  fn jackpot(&self) -> PandaStack {
+    PandaStack::new("/jackpot")
+  }
 
-      Ok(response)
-    }
+  fn jackpot_enabled_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/jackpot-enabled")
   }
 
-  fn calculate_random_multiplier(&self, txid: &Txid) -> Result<u8> {
-    let block_hash = self.block_hash()?;
-    let txid_bytes = txid.as_byte_array();
+  fn jackpot_enabled(&self) -> bool {
+    self.jackpot_enabled_pointer().get_value::<u8>() == 1
+  }
 
-    let value = block_hash[31].wrapping_add(txid_bytes[31]);
+  // Roll credits charged per insured panda, zero while insurance is off
+  fn insurance_premium_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/insurance-premium")
+  }
 
-    Ok(if value < 141 { 0 } else { 2 })
+  fn insurance_premium(&self) -> u128 {
+    self.insurance_premium_pointer().get_value::<u128>()
   }
 
-  fn instances_pointer(&self) -> StoragePointer {
-    StoragePointer::from_keyword("/instances")
+  fn pity_threshold_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/pity-threshold")
   }
 
-  fn instances_count(&self) -> u128 {
-    self.instances_pointer().get_value::<u128>()
+  fn pity_threshold(&self) -> u128 {
+    self.pity_threshold_pointer().get_value::<u128>()
   }
 
-  fn set_instances_count(&self, count: u128) {
-    self.instances_pointer().set_value::<u128>(count);
+  fn threshold_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/threshold")
   }
 
-  fn add_instance(&self, instance_id: &AlkaneId) -> Result<u128> {
-    let count = self.instances_count();
-    let new_count = count.checked_add(1)
-      .ok_or_else(|| anyhow!("instances count overflow"))?;
+  fn parlay_threshold_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/parlay-threshold")
+  }
 
-    let mut bytes = Vec::with_capacity(32);
-    bytes.extend_from_slice(&instance_id.block.to_le_bytes());
-    bytes.extend_from_slice(&instance_id.tx.to_le_bytes());
+  // Falls back to PARLAY_THRESHOLD until one is configured
+  fn parlay_threshold(&self) -> u8 {
+    match self.parlay_threshold_pointer().get_value::<u8>() {
+      0 => PARLAY_THRESHOLD,
+      threshold => threshold,
+    }
+  }
 
-    let bytes_vec = new_count.to_le_bytes().to_vec();
-    let mut instance_pointer = self.instances_pointer().select(&bytes_vec);
-    instance_pointer.set(Arc::new(bytes));
-    
-    self.set_instances_count(new_count);
-    
-    Ok(new_count)
+  fn tier_handicap_pointer(&self, tier: PrizeTier) -> StoragePointer {
+    StoragePointer::from_keyword("/config/tier-handicap/").select(&vec![tier as u8])
   }
 
-  fn pop_instance(&self) -> Result<AlkaneId> {
-    let count = self.instances_count();
+  fn tier_handicap(&self, tier: PrizeTier) -> u8 {
+    self.tier_handicap_pointer(tier).get_value::<u8>()
+  }
 
-    let new_count = count.checked_sub(1)
-      .ok_or_else(|| anyhow!("instances count underflow"))?;
+  fn loss_streak_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/streaks/").select(&alkane_id_to_bytes(caller))
+  }
 
-    let instance_id = self.lookup_instance(count - 1)?;
-    
-    // Remove the instance by setting it to empty
-    let bytes_vec = count.to_le_bytes().to_vec();
-    let mut instance_pointer = self.instances_pointer().select(&bytes_vec);
-    instance_pointer.set(Arc::new(Vec::new()));
-    
-    self.set_instances_count(new_count);
-    
-    Ok(instance_id)
+  fn loss_streak(&self, caller: &AlkaneId) -> u128 {
+    self.loss_streak_pointer(caller).get_value::<u128>()
   }
 
-  fn lookup_instance(&self, index: u128) -> Result<AlkaneId> {
-    let bytes_vec = (index + 1).to_le_bytes().to_vec();
-    let instance_pointer = self.instances_pointer().select(&bytes_vec);
-    
-    let bytes = instance_pointer.get();
-    if bytes.len() != 32 {
-      return Err(anyhow!("Invalid instance data length"));
+  fn set_loss_streak(&self, caller: &AlkaneId, streak: u128) {
+    self.loss_streak_pointer(caller).set_value::<u128>(streak);
+  }
+
+  fn streak_stake_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/streak-stakes/").select(&alkane_id_to_bytes(caller))
+  }
+
+  fn win_streak_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/win-streaks/").select(&alkane_id_to_bytes(caller))
+  }
+
+  fn win_streak(&self, caller: &AlkaneId) -> u128 {
+    self.win_streak_pointer(caller).get_value::<u128>()
+  }
+
+  fn set_win_streak(&self, caller: &AlkaneId, streak: u128) {
+    self.win_streak_pointer(caller).set_value::<u128>(streak);
+  }
+
+  fn depositor_pointer(&self, id: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/depositors/").select(&alkane_id_to_bytes(id))
+  }
+
+  fn depositor(&self, id: &AlkaneId) -> Result<Option<AlkaneId>> {
+    let bytes = self.depositor_pointer(id).get();
+    if bytes.len() == 0 {
+      return Ok(None);
     }
 
-    let block_bytes = &bytes[..16];
-    let tx_bytes = &bytes[16..];
+    Ok(Some(alkane_id_from_bytes(&bytes)?))
+  }
+
+  fn set_depositor(&self, id: &AlkaneId, depositor: &AlkaneId) {
+    self.depositor_pointer(id).set(Arc::new(alkane_id_to_bytes(depositor)));
+  }
+
+  fn clear_depositor(&self, id: &AlkaneId) {
+    self.depositor_pointer(id).set(Arc::new(Vec::new()));
+  }
+
+  fn get_panda_stack_count(&self, tier: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.pool(PrizeTier::from_u128(tier)?).count().to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  fn get_jackpot_size(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.jackpot().count().to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  fn get_my_streak(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
 
-    let block = u128::from_le_bytes(block_bytes.try_into().unwrap());
-    let tx = u128::from_le_bytes(tx_bytes.try_into().unwrap());
+    response.data = self.loss_streak(&context.caller).to_le_bytes().to_vec();
 
-    Ok(AlkaneId { block, tx })
+    Ok(response)
   }
 
-  fn get_panda_stack_count(&self) -> Result<CallResponse> {
+  fn get_my_win_streak(&self) -> Result<CallResponse> {
     let context = self.context()?;
     let mut response = CallResponse::forward(&context.incoming_alkanes);
 
-    response.data = self.instances_count().to_le_bytes().to_vec();
+    response.data = self.win_streak(&context.caller).to_le_bytes().to_vec();
 
     Ok(response)
   }
 
-  fn get_panda_stack(&self) -> Result<CallResponse> {
+  fn get_panda_stack(&self, tier: u128) -> Result<CallResponse> {
     let context = self.context()?;
     let mut response = CallResponse::forward(&context.incoming_alkanes);
 
-    let count = self.instances_count();
+    let pool = self.pool(PrizeTier::from_u128(tier)?);
+    let count = pool.count();
     let mut panda_ids = Vec::new();
 
     for i in 0..count {
-      let instance_id = self.lookup_instance(i)?;
-      let mut bytes = Vec::with_capacity(32);
-      bytes.extend_from_slice(&instance_id.block.to_le_bytes());
-      bytes.extend_from_slice(&instance_id.tx.to_le_bytes());
-      panda_ids.push(bytes);
+      let instance_id = pool.get(i)?;
+      panda_ids.push(alkane_id_to_bytes(&instance_id));
     }
 
     let mut flattened = Vec::new();
@@ -273,52 +1797,196 @@ impl PandaRoll {
     Ok(response)
   }
 
-  fn get_panda_stack_json(&self) -> Result<CallResponse> {
+  // 1, the pool's tier and the panda's index if a prize pool holds it, else 0
+  fn contains_panda(&self, block: u128, tx: u128) -> Result<CallResponse> {
     let context = self.context()?;
     let mut response = CallResponse::forward(&context.incoming_alkanes);
 
-    let count = self.instances_count();
-    let mut panda_ids = Vec::new();
+    let id = AlkaneId { block, tx };
+    response.data = vec![0];
 
-    for i in 0..count {
-      let instance_id = self.lookup_instance(i)?;
-      panda_ids.push(format!("{}:{}", instance_id.block, instance_id.tx));
+    for tier in [PrizeTier::Common, PrizeTier::Rare] {
+      if let Some(index) = self.pool(tier).find(&id)? {
+        response.data = vec![1, tier as u8];
+        response.data.extend_from_slice(&index.to_le_bytes());
+        break;
+      }
+    }
+
+    Ok(response)
+  }
+
+  // 1 and the panda's rarity tier if deposits and rolls accept it, else 0
+  fn is_valid_panda(&self, block: u128, tx: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let id = AlkaneId { block, tx };
+    response.data = if self.accepts_panda(&id)? { vec![1, self.rarity(tx)] } else { vec![0] };
+
+    Ok(response)
+  }
+
+  fn get_panda_at_index(&self, tier: u128, index: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let pool = self.pool(PrizeTier::from_u128(tier)?);
+    if index >= pool.count() {
+      return Err(anyhow!("Index {} is out of range for a pool of {}", index, pool.count()));
+    }
+
+    response.data = alkane_id_to_bytes(&pool.get(index)?);
+
+    Ok(response)
+  }
+
+  fn get_stack_merkle_root(&self, tier: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.pool(PrizeTier::from_u128(tier)?).merkle_root()?.to_vec();
+
+    Ok(response)
+  }
+
+  // The entry's slot (u128), its id (32 bytes) and the sibling hashes from the
+  // leaf up (32 bytes each), one per level of the pool's tree, which deepens as
+  // the pool grows. The leaf is sha256(0x00 ++ id) and each parent
+  // sha256(0x01 ++ left ++ right), which folds up to GetStackMerkleRoot.
+  fn get_stack_proof(&self, tier: u128, index: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let pool = self.pool(PrizeTier::from_u128(tier)?);
+    let (slot, siblings) = pool.merkle_proof(index)?;
+
+    response.data = slot.to_le_bytes().to_vec();
+    response.data.extend_from_slice(&alkane_id_to_bytes(&pool.get(index)?));
+    for sibling in siblings {
+      response.data.extend_from_slice(&sibling);
+    }
+
+    Ok(response)
+  }
+
+  // The pool's total count followed by up to `limit` ids starting at `offset`
+  fn get_panda_stack_page(&self, tier: u128, offset: u128, limit: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let pool = self.pool(PrizeTier::from_u128(tier)?);
+    let count = pool.count();
+
+    let mut data = count.to_le_bytes().to_vec();
+    data.extend(self.packed_range(&pool, offset, limit.min(MAX_STACK_PAGE))?);
+
+    response.data = data;
+    Ok(response)
+  }
+
+  // Up to `count` packed ids from `start`, stopping at the end of the pool
+  fn get_panda_stack_range(&self, tier: u128, start: u128, count: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    if count > MAX_STACK_PAGE {
+      return Err(anyhow!("At most {} pandas per range", MAX_STACK_PAGE));
     }
 
-    response.data = serde_json::to_string(&panda_ids)?.into_bytes();
+    response.data = self.packed_range(&self.pool(PrizeTier::from_u128(tier)?), start, count)?;
+
     Ok(response)
   }
 
-  fn current_block(&self) -> Result<Block> {
-    Ok(AuxpowBlock::parse(&mut Cursor::<Vec<u8>>::new(self.block()))?.to_consensus())
+  fn packed_range(&self, pool: &PandaStack, start: u128, count: u128) -> Result<Vec<u8>> {
+    let end = start.saturating_add(count).min(pool.count());
+
+    let mut data = Vec::new();
+    for i in start.min(end)..end {
+      data.extend(alkane_id_to_bytes(&pool.get(i)?));
+    }
+
+    Ok(data)
   }
 
   fn block_hash(&self) -> Result<Vec<u8>> {
-    let hash = self.current_block()?.block_hash().as_byte_array().to_vec();
+    if let Some(hash) = self.block_hash.get() {
+      return Ok(hash.clone());
+    }
+
+    // Hashes the header straight from the raw block rather than parsing the
+    // whole thing, transactions and all
+    let block = self.block();
+    if block.len() < BLOCK_HEADER_SIZE {
+      return Err(anyhow!("Block is too short for a header"));
+    }
+
+    let hash = sha256d::Hash::hash(&block[..BLOCK_HEADER_SIZE]).to_byte_array().to_vec();
+    let _ = self.block_hash.set(hash.clone());
+
     Ok(hash)
   }
 
   fn transaction_id(&self) -> Result<Txid> {
-    Ok(
-      consensus_decode::<Transaction>(&mut std::io::Cursor::new(self.transaction()))?
-        .compute_txid(),
-    )
+    if let Some(txid) = self.txid.get() {
+      return Ok(*txid);
+    }
+
+    let txid = consensus_decode::<Transaction>(&mut std::io::Cursor::new(self.transaction()))?
+      .compute_txid();
+    let _ = self.txid.set(txid);
+
+    Ok(txid)
   }
 
   fn has_tx_hash(&self, txid: &Txid) -> bool {
-    StoragePointer::from_keyword("/tx-hashes/")
-      .select(&txid.as_byte_array().to_vec())
-      .get_value::<u8>()
-      == 1
+    match self.roll_guard_pointer(txid) {
+      Ok(pointer) => pointer.get_value::<u8>() == 1,
+      Err(_) => false,
+    }
   }
 
   fn add_tx_hash(&self, txid: &Txid) -> Result<()> {
-    StoragePointer::from_keyword("/tx-hashes/")
-      .select(&txid.as_byte_array().to_vec())
-      .set_value::<u8>(0x01);
+    self.roll_guard_pointer(txid)?.set_value::<u8>(0x01);
+
+    // A running count and XOR of the guarded keys sums the set up for snapshots
+    let mut key = txid.as_byte_array().to_vec();
+    key.extend_from_slice(&self.context()?.vout.to_le_bytes());
+    let (count, mut digest) = self.tx_hash_summary();
+    for (byte, hash_byte) in digest.iter_mut().zip(sha256::Hash::hash(&key).to_byte_array()) {
+      *byte ^= hash_byte;
+    }
+    self.set_tx_hash_summary(count + 1, &digest);
 
     Ok(())
   }
+
+  // Guarded roll count and the XOR of each guard key's SHA-256
+  fn tx_hash_summary(&self) -> (u128, [u8; 32]) {
+    let summary = StoragePointer::from_keyword("/tx-hash-summary");
+
+    (
+      summary.keyword("/count").get_value::<u128>(),
+      summary.keyword("/digest").get().as_slice().try_into().unwrap_or([0u8; 32]),
+    )
+  }
+
+  fn set_tx_hash_summary(&self, count: u128, digest: &[u8; 32]) {
+    let summary = StoragePointer::from_keyword("/tx-hash-summary");
+
+    summary.keyword("/count").set_value::<u128>(count);
+    summary.keyword("/digest").set(Arc::new(digest.to_vec()));
+  }
+
+  // One roll per protostone, so a transaction can batch rolls across its protostones
+  fn roll_guard_pointer(&self, txid: &Txid) -> Result<StoragePointer> {
+    Ok(
+      StoragePointer::from_keyword("/tx-hashes/")
+        .select(&txid.as_byte_array().to_vec())
+        .select(&self.context()?.vout.to_le_bytes().to_vec())
+    )
+  }
 }
 
 declare_alkane! {
@@ -326,3 +1994,95 @@ declare_alkane! {
     type Message = PandaRollMessage;
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn digest_value_folds_every_byte() {
+    assert_eq!(digest_value(&[0u8; 32]), 0);
+
+    let mut digest = [0u8; 32];
+    digest[0] = 0b1010_0000;
+    digest[31] = 0b0000_0101;
+    assert_eq!(digest_value(&digest), 0b1010_0101);
+
+    // Equal bytes cancel out wherever they sit
+    digest[7] = 0x3c;
+    digest[19] = 0x3c;
+    assert_eq!(digest_value(&digest), 0b1010_0101);
+  }
+
+  #[test]
+  fn risk_levels_order_their_bands_from_the_top() {
+    for bands in RISK_LEVELS {
+      assert!(!bands.is_empty());
+
+      for pair in bands.windows(2) {
+        assert!(pair[0].0 > pair[1].0, "thresholds must descend");
+        assert!(pair[0].1 > pair[1].1, "multipliers must descend");
+      }
+
+      assert!(bands.iter().all(|(_, multiplier)| *multiplier >= 2));
+    }
+  }
+
+  #[test]
+  fn every_risk_level_wins_on_the_jackpot_value() {
+    let roll = PandaRoll::default();
+
+    for risk in 0..RISK_LEVELS.len() as u128 {
+      let bands = roll.risk_bands(risk, 155).unwrap();
+      assert!(roll.calculate_outcome(JACKPOT_VALUE, &bands, 0) == RollOutcome::Win(bands[0].1));
+      assert!(roll.calculate_outcome(0, &bands, 0) == RollOutcome::Lose);
+    }
+
+    assert!(roll.risk_bands(RISK_LEVELS.len() as u128, 155).is_err());
+  }
+
+  #[test]
+  fn threshold_moves_only_the_tuned_risk_2x_band() {
+    let roll = PandaRoll::default();
+
+    let tuned = roll.risk_bands(TUNED_RISK, 141).unwrap();
+    let default = RISK_LEVELS[TUNED_RISK as usize];
+    assert_eq!(tuned.last(), Some(&(141, 2)));
+    assert_eq!(&tuned[..tuned.len() - 1], &default[..default.len() - 1]);
+
+    // The near-miss push band moves down with it
+    assert!(roll.calculate_outcome(129, &tuned, 0) == RollOutcome::Lose);
+    assert!(roll.calculate_outcome(130, &tuned, 0) == RollOutcome::Push);
+    assert!(roll.calculate_outcome(140, &tuned, 0) == RollOutcome::Push);
+    assert!(roll.calculate_outcome(141, &tuned, 0) == RollOutcome::Win(2));
+
+    for risk in (0..RISK_LEVELS.len() as u128).filter(|risk| *risk != TUNED_RISK) {
+      assert_eq!(roll.risk_bands(risk, 141).unwrap(), RISK_LEVELS[risk as usize].to_vec());
+    }
+  }
+
+  // Expected return in basis points over every entropy value, as GetOdds works it out
+  fn expected_return(risk: u128, threshold: u8) -> u128 {
+    let roll = PandaRoll::default();
+    let bands = roll.risk_bands(risk, threshold).unwrap();
+
+    (0..=u8::MAX).map(|value| roll.calculate_outcome(value, &bands, 0).multiplier()).sum::<u128>() * 10_000 / 256
+  }
+
+  #[test]
+  fn default_odds_keep_a_house_edge() {
+    // 91 values at 2x, 8 at 3x, 2 at 10x and 11 near-miss pushes at 1x return
+    // 237 of every 256 staked, a 7.43% edge
+    assert_eq!(expected_return(TUNED_RISK, 155), 9_257);
+  }
+
+  #[test]
+  fn raising_the_threshold_raises_the_house_edge() {
+    let mut last = expected_return(TUNED_RISK, 1);
+    for threshold in 2..246u8 {
+      let current = expected_return(TUNED_RISK, threshold);
+      assert!(current < last);
+      last = current;
+    }
+  }
+}
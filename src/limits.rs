@@ -0,0 +1,80 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+
+use crate::{alkane_id_to_bytes, PandaRoll};
+
+impl PandaRoll {
+  // Caps each caller at `cap` rolls per `window` blocks. A cap of 0 removes the limit.
+  pub(crate) fn set_roll_limit(&self, window: u128, cap: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    if cap != 0 && window == 0 {
+      return Err(anyhow!("Roll limit window must be at least 1 block"));
+    }
+
+    let config = self.roll_limit_pointer();
+    config.keyword("/window").set_value::<u128>(window);
+    config.keyword("/cap").set_value::<u128>(cap);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn get_my_remaining_rolls(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.remaining_rolls(&context.caller).to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Counts a roll against the caller's allowance for the current window
+  pub(crate) fn count_roll(&self, caller: &AlkaneId) -> Result<()> {
+    if self.roll_cap() == 0 {
+      return Ok(());
+    }
+
+    if self.remaining_rolls(caller) == 0 {
+      return Err(anyhow!("Roll limit reached for this window"));
+    }
+
+    let mut rolls = self.window_rolls_pointer(caller);
+    let count = rolls.get_value::<u128>();
+    rolls.set_value::<u128>(count + 1);
+
+    Ok(())
+  }
+
+  fn remaining_rolls(&self, caller: &AlkaneId) -> u128 {
+    let cap = self.roll_cap();
+    if cap == 0 {
+      return u128::MAX;
+    }
+
+    cap.saturating_sub(self.window_rolls_pointer(caller).get_value::<u128>())
+  }
+
+  // Rolls are counted per caller and per window, so old windows simply stop being read
+  fn window_rolls_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    let window = self.roll_limit_pointer().keyword("/window").get_value::<u128>().max(1);
+    let window_index = self.height() as u128 / window;
+
+    StoragePointer::from_keyword("/roll-counts/")
+      .select(&alkane_id_to_bytes(caller))
+      .select(&window_index.to_le_bytes().to_vec())
+  }
+
+  fn roll_cap(&self) -> u128 {
+    self.roll_limit_pointer().keyword("/cap").get_value::<u128>()
+  }
+
+  pub(crate) fn roll_limit_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/roll-limit")
+  }
+}
@@ -0,0 +1,67 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::response::CallResponse;
+
+use anyhow::{anyhow, Result};
+
+use crate::PandaRoll;
+
+// Longest block hash suffix a lucky pattern can match
+const MAX_LUCKY_PATTERN_LENGTH: u128 = 16;
+
+impl PandaRoll {
+  // A block is lucky when its hash, as block explorers display it, ends in the
+  // low `length` bytes of `pattern`. A length of 0 switches lucky blocks off.
+  pub(crate) fn set_lucky_block(&self, pattern: u128, length: u128, boost: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    if length > MAX_LUCKY_PATTERN_LENGTH {
+      return Err(anyhow!("Lucky pattern is too long"));
+    }
+
+    let boost = u8::try_from(boost).map_err(|_| anyhow!("Invalid lucky block boost"))?;
+
+    let config = self.lucky_block_pointer();
+    config.keyword("/pattern").set_value::<u128>(pattern);
+    config.keyword("/length").set_value::<u128>(length);
+    config.keyword("/boost").set_value::<u8>(boost);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn is_lucky_block(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = vec![self.lucky_boost()?];
+
+    Ok(response)
+  }
+
+  // Entropy points added to every roll in the current block, 0 outside lucky blocks
+  pub(crate) fn lucky_boost(&self) -> Result<u8> {
+    let config = self.lucky_block_pointer();
+    let length = config.keyword("/length").get_value::<u128>() as usize;
+    if length == 0 {
+      return Ok(0);
+    }
+
+    let pattern = config.keyword("/pattern").get_value::<u128>().to_le_bytes();
+    let block_hash = self.block_hash()?;
+
+    // The hash is stored byte-reversed, so its displayed tail comes first. The
+    // other end holds the proof-of-work zeros, which every block shares.
+    if block_hash.len() < length || block_hash[..length] != pattern[..length] {
+      return Ok(0);
+    }
+
+    Ok(config.keyword("/boost").get_value::<u8>())
+  }
+
+  pub(crate) fn lucky_block_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/lucky-block")
+  }
+}
@@ -0,0 +1,224 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, PandaRoll, PrizeTier};
+
+const SIDE_AT_OR_BELOW: u128 = 0;
+const SIDE_ABOVE: u128 = 1;
+
+const STATE_OPEN: u8 = 1;
+const STATE_SETTLED: u8 = 2;
+
+// Parimutuel markets on the number of pandas in the prize pools at a target
+// height. Positions are taken in the payment token until then, then anyone can
+// settle and the winning side splits the whole pot in proportion to its stakes.
+impl PandaRoll {
+  pub(crate) fn create_market(&self, target: u128, height: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    if height <= self.height() as u128 {
+      return Err(anyhow!("Market must settle at a future height"));
+    }
+
+    let market_id = self.markets_count();
+    self.markets_pointer().set_value::<u128>(market_id + 1);
+
+    // Positions stay in the token the market opened with
+    let market = self.market_pointer(market_id);
+    market.keyword("/token").set(Arc::new(alkane_id_to_bytes(&self.payment_token()?)));
+    market.keyword("/target").set_value::<u128>(target);
+    market.keyword("/height").set_value::<u128>(height);
+    market.keyword("/state").set_value::<u8>(STATE_OPEN);
+
+    let mut latest = self.observations_pointer().keyword("/latest-target");
+    if height > latest.get_value::<u128>() {
+      latest.set_value::<u128>(height);
+    }
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = market_id.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn take_position(&self, market_id: u128, side: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    if side != SIDE_AT_OR_BELOW && side != SIDE_ABOVE {
+      return Err(anyhow!("Invalid market side"));
+    }
+
+    let market = self.market_pointer(market_id);
+    if market.keyword("/state").get_value::<u8>() != STATE_OPEN {
+      return Err(anyhow!("Market is not open"));
+    }
+
+    if self.height() as u128 >= market.keyword("/height").get_value::<u128>() {
+      return Err(anyhow!("Market is closed for positions"));
+    }
+
+    let token = alkane_id_from_bytes(&market.keyword("/token").get())?;
+    let amount = self.incoming_amount(&context, &token);
+    if amount == 0 {
+      return Err(anyhow!("Must send the market token"));
+    }
+
+    let remaining = self.take_incoming(&context, &token, amount)?;
+
+    let mut position = self.market_side_pointer(market_id, side).select(&alkane_id_to_bytes(&context.caller));
+    let staked = position.get_value::<u128>().checked_add(amount)
+      .ok_or_else(|| anyhow!("market position overflow"))?;
+    position.set_value::<u128>(staked);
+
+    let mut total = self.market_side_pointer(market_id, side);
+    let side_total = total.get_value::<u128>().checked_add(amount)
+      .ok_or_else(|| anyhow!("market total overflow"))?;
+    total.set_value::<u128>(side_total);
+
+    Ok(CallResponse::forward(&remaining))
+  }
+
+  // Settles on the pool size at the target height, however long after it the
+  // settling call comes
+  pub(crate) fn settle_market(&self, market_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let market = self.market_pointer(market_id);
+    if market.keyword("/state").get_value::<u8>() != STATE_OPEN {
+      return Err(anyhow!("Market is not open"));
+    }
+
+    let height = market.keyword("/height").get_value::<u128>();
+    if (self.height() as u128) < height {
+      return Err(anyhow!("Market cannot be settled yet"));
+    }
+
+    let count = self.pool_size_at(height);
+    let side = if count > market.keyword("/target").get_value::<u128>() { SIDE_ABOVE } else { SIDE_AT_OR_BELOW };
+
+    market.keyword("/outcome").set_value::<u128>(side);
+    market.keyword("/state").set_value::<u8>(STATE_SETTLED);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = count.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn claim_market(&self, market_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let market = self.market_pointer(market_id);
+    if market.keyword("/state").get_value::<u8>() != STATE_SETTLED {
+      return Err(anyhow!("Market is not settled"));
+    }
+
+    let outcome = market.keyword("/outcome").get_value::<u128>();
+    let winning_total = self.market_side_pointer(market_id, outcome).get_value::<u128>();
+    let losing_total = self.market_side_pointer(market_id, 1 - outcome).get_value::<u128>();
+
+    // With nobody on the winning side every position is refunded
+    let sides = if winning_total == 0 { vec![SIDE_AT_OR_BELOW, SIDE_ABOVE] } else { vec![outcome] };
+
+    let mut payout = 0u128;
+    for side in sides {
+      let mut position = self.market_side_pointer(market_id, side).select(&alkane_id_to_bytes(&context.caller));
+      let staked = position.get_value::<u128>();
+      position.set_value::<u128>(0);
+
+      payout = payout.checked_add(if winning_total == 0 {
+        staked
+      } else {
+        staked.checked_mul(losing_total)
+          .map(|share| staked + share / winning_total)
+          .ok_or_else(|| anyhow!("market payout overflow"))?
+      }).ok_or_else(|| anyhow!("market payout overflow"))?;
+    }
+
+    if payout == 0 {
+      return Err(anyhow!("Nothing to claim for this market"));
+    }
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.alkanes.0.push(AlkaneTransfer {
+      id: alkane_id_from_bytes(&market.keyword("/token").get())?,
+      value: payout,
+    });
+
+    Ok(response)
+  }
+
+  // Records the pool size ahead of the first change to the pools in each block,
+  // for as long as some market's target height has not been observed yet. The
+  // pools then hold that size from a target height up to the next observation.
+  pub(crate) fn observe_markets(&self) {
+    let height = self.height() as u128;
+    let mut observations = self.observations_pointer();
+
+    let mut observed = observations.keyword("/height");
+    let last = observed.get_value::<u128>();
+    if last >= height || last >= observations.keyword("/latest-target").get_value::<u128>() {
+      return;
+    }
+    observed.set_value::<u128>(height);
+
+    let index = observations.get_value::<u128>();
+    let entry = observations.select(&index.to_le_bytes().to_vec());
+    entry.keyword("/height").set_value::<u128>(height);
+    entry.keyword("/size").set_value::<u128>(self.pool_size());
+    observations.set_value::<u128>(index + 1);
+  }
+
+  // The first observation at or after `height`, or the current size when the
+  // pools have not changed since
+  fn pool_size_at(&self, height: u128) -> u128 {
+    let observations = self.observations_pointer();
+    let entry = |index: u128| observations.select(&index.to_le_bytes().to_vec());
+
+    let (mut low, mut high) = (0u128, observations.get_value::<u128>());
+    while low < high {
+      let middle = low + (high - low) / 2;
+      if entry(middle).keyword("/height").get_value::<u128>() < height {
+        low = middle + 1;
+      } else {
+        high = middle;
+      }
+    }
+
+    if low == observations.get_value::<u128>() {
+      return self.pool_size();
+    }
+
+    entry(low).keyword("/size").get_value::<u128>()
+  }
+
+  fn pool_size(&self) -> u128 {
+    self.pool(PrizeTier::Common).count() + self.pool(PrizeTier::Rare).count()
+  }
+
+  fn observations_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/market-observations")
+  }
+
+  fn markets_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/markets/")
+  }
+
+  fn markets_count(&self) -> u128 {
+    self.markets_pointer().get_value::<u128>()
+  }
+
+  fn market_pointer(&self, market_id: u128) -> StoragePointer {
+    self.markets_pointer().select(&market_id.to_le_bytes().to_vec())
+  }
+
+  // The side's total stake lives at the base key, each caller's position under it
+  fn market_side_pointer(&self, market_id: u128, side: u128) -> StoragePointer {
+    self.market_pointer(market_id).keyword("/sides/").select(&side.to_le_bytes().to_vec())
+  }
+}
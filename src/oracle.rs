@@ -0,0 +1,54 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{cellpack::Cellpack, id::AlkaneId, parcel::AlkaneTransferParcel, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, PandaRoll};
+
+// An external alkane that can stand in for local block and txid entropy. It is
+// staticcalled with `opcode` and must answer with a non-empty random word.
+impl PandaRoll {
+  // A block of 0 clears the oracle and goes back to local entropy
+  pub(crate) fn set_oracle(&self, block: u128, tx: u128, opcode: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    let oracle = self.oracle_pointer();
+    if block == 0 {
+      oracle.keyword("/id").set(Arc::new(Vec::new()));
+    } else {
+      oracle.keyword("/id").set(Arc::new(alkane_id_to_bytes(&AlkaneId { block, tx })));
+    }
+    oracle.keyword("/opcode").set_value::<u128>(opcode);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn oracle_word(&self) -> Result<Option<Vec<u8>>> {
+    let oracle = self.oracle_pointer();
+    let id = oracle.keyword("/id").get();
+    if id.len() == 0 {
+      return Ok(None);
+    }
+
+    let cellpack = Cellpack {
+      target: alkane_id_from_bytes(&id)?,
+      inputs: vec![oracle.keyword("/opcode").get_value::<u128>()],
+    };
+    let word = self.staticcall(&cellpack, &AlkaneTransferParcel::default(), self.fuel())?.data;
+
+    if word.len() == 0 {
+      return Err(anyhow!("Oracle returned no randomness"));
+    }
+
+    Ok(Some(word))
+  }
+
+  pub(crate) fn oracle_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/oracle")
+  }
+}
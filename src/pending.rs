@@ -0,0 +1,317 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{
+  context::Context, id::AlkaneId,
+  parcel::{AlkaneTransfer, AlkaneTransferParcel}, response::CallResponse,
+};
+
+use anyhow::{anyhow, Result};
+use bitcoin::Txid;
+use bitcoin::hashes::Hash;
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, stack::PandaStack, PandaRoll, PrizeTier, RollOutcome};
+
+const STATE_QUEUED: u8 = 1;
+pub(crate) const STATE_COMMITTED: u8 = 2;
+const STATE_RESOLVED: u8 = 3;
+const STATE_CLAIMED: u8 = 4;
+const STATE_REFUNDED: u8 = 5;
+
+// Most queued rolls a single ProcessQueue call settles
+const MAX_QUEUE_BATCH: u128 = 10;
+
+// Blocks a committed roll waits before it can be resolved
+pub(crate) const RESOLVE_DELAY_BLOCKS: u64 = 3;
+
+// Blocks after which an unresolved commitment can be refunded instead
+const COMMIT_EXPIRY_BLOCKS: u64 = 144;
+
+// Blocks a queued roll waits for the pools before its stake is handed back
+const QUEUE_EXPIRY_BLOCKS: u64 = 144;
+
+// Rolls that don't settle in the transaction that made them wait here, keyed by
+// that txid, with the staked pandas escrowed alongside. Queued rolls wait for the
+// prize pools to refill and settle strictly in arrival order, or expire after
+// QUEUE_EXPIRY_BLOCKS. Committed rolls wait for a later block hash. Either way
+// the owner claims the result afterwards.
+impl PandaRoll {
+  pub(crate) fn enqueue_roll(&self, context: &Context, txid: &Txid, risk: u128, tier: PrizeTier, salt: u128) -> Result<CallResponse> {
+    let key = self.escrow_roll(context, txid, risk, tier, STATE_QUEUED)?;
+    self.pending_roll_pointer(&key).keyword("/salt").set_value::<u128>(salt);
+
+    let queue = self.roll_queue_pointer();
+    let tail = queue.keyword("/tail").get_value::<u128>();
+    queue.keyword("/entries/").select(&tail.to_le_bytes().to_vec()).set(Arc::new(key.clone()));
+    queue.keyword("/tail").set_value::<u128>(tail + 1);
+
+    let mut response = CallResponse::default();
+    response.data = key;
+
+    Ok(response)
+  }
+
+  // Escrows the stake now and rolls on the hash of a block at least
+  // RESOLVE_DELAY_BLOCKS later, which nobody can know at commit time
+  pub(crate) fn commit_roll(&self, risk: u128, prize_tier: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+    let tier = PrizeTier::from_u128(prize_tier)?;
+
+    // Fail early on an unknown risk level rather than at resolution
+    self.risk_level(risk)?;
+    self.validate_stake(&context, &txid)?;
+
+    self.add_tx_hash(&txid)?;
+    self.count_roll(&context.caller)?;
+
+    let key = self.escrow_roll(&context, &txid, risk, tier, STATE_COMMITTED)?;
+
+    let mut response = CallResponse::default();
+    response.data = key;
+
+    Ok(response)
+  }
+
+  // Anyone can resolve a commitment once the delay has passed
+  pub(crate) fn resolve_roll(&self, txid_lo: u128, txid_hi: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let key = txid_key(txid_lo, txid_hi);
+
+    let pending = self.pending_roll_pointer(&key);
+    if pending.keyword("/state").get_value::<u8>() != STATE_COMMITTED {
+      return Err(anyhow!("Roll is not awaiting resolution"));
+    }
+
+    if self.height() < pending.keyword("/height").get_value::<u64>() + RESOLVE_DELAY_BLOCKS {
+      return Err(anyhow!("Roll cannot be resolved yet"));
+    }
+
+    if !self.settle_pending_roll(&context, &key)? {
+      return Err(anyhow!("Not enough Pandas available to roll"));
+    }
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = pending.keyword("/multiplier").get_value::<u128>().to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Hands the escrowed stake back when a commitment was never resolved in time
+  pub(crate) fn refund_commit(&self, txid_lo: u128, txid_hi: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let key = txid_key(txid_lo, txid_hi);
+
+    let pending = self.pending_roll_pointer(&key);
+    if self.pending_owner(&key)? != context.caller {
+      return Err(anyhow!("Caller did not make this roll"));
+    }
+
+    if pending.keyword("/state").get_value::<u8>() != STATE_COMMITTED {
+      return Err(anyhow!("Roll is not awaiting resolution"));
+    }
+
+    if self.height() < pending.keyword("/height").get_value::<u64>() + COMMIT_EXPIRY_BLOCKS {
+      return Err(anyhow!("Commitment has not expired yet"));
+    }
+
+    pending.keyword("/state").set_value::<u8>(STATE_REFUNDED);
+    self.settle_side_bets(&key, None);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    for panda in self.pending_stake(&key).drain()? {
+      response.alkanes.0.push(AlkaneTransfer {
+        id: panda,
+        value: 1u128,
+      });
+    }
+
+    Ok(response)
+  }
+
+  // Anyone can settle queued rolls once deposits have refilled the pools
+  pub(crate) fn process_queue(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let queue = self.roll_queue_pointer();
+
+    let mut head = queue.keyword("/head").get_value::<u128>();
+    let tail = queue.keyword("/tail").get_value::<u128>();
+    let mut processed = 0u128;
+
+    while head < tail && processed < MAX_QUEUE_BATCH {
+      let key = queue.keyword("/entries/").select(&head.to_le_bytes().to_vec()).get().to_vec();
+
+      // Later rolls never jump ahead of one still waiting for prizes, unless it
+      // has waited so long it expires
+      if !self.settle_pending_roll(&context, &key)? && !self.expire_queued_roll(&key)? {
+        break;
+      }
+
+      head += 1;
+      processed += 1;
+    }
+
+    queue.keyword("/head").set_value::<u128>(head);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = processed.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Pays out a settled roll, queued or committed, to the player who made it
+  pub(crate) fn claim_winnings(&self, txid_lo: u128, txid_hi: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let key = txid_key(txid_lo, txid_hi);
+
+    let pending = self.pending_roll_pointer(&key);
+    if self.pending_owner(&key)? != context.caller {
+      return Err(anyhow!("Caller did not make this roll"));
+    }
+
+    if pending.keyword("/state").get_value::<u8>() != STATE_RESOLVED {
+      return Err(anyhow!("Roll has nothing to claim"));
+    }
+
+    pending.keyword("/state").set_value::<u8>(STATE_CLAIMED);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    for panda in self.pending_payout(&key).drain()? {
+      response.alkanes.0.push(AlkaneTransfer {
+        id: panda,
+        value: 1u128,
+      });
+    }
+
+    Ok(response)
+  }
+
+  fn escrow_roll(&self, context: &Context, txid: &Txid, risk: u128, tier: PrizeTier, state: u8) -> Result<Vec<u8>> {
+    let key = txid.as_byte_array().to_vec();
+
+    // Pending rolls are claimed by txid alone, so only one per transaction
+    let pending = self.pending_roll_pointer(&key);
+    if pending.keyword("/state").get_value::<u8>() != 0 {
+      return Err(anyhow!("Transaction already has a pending roll"));
+    }
+
+    pending.keyword("/owner").set(Arc::new(alkane_id_to_bytes(&context.caller)));
+    pending.keyword("/risk").set_value::<u128>(risk);
+    pending.keyword("/tier").set_value::<u8>(tier as u8);
+    pending.keyword("/height").set_value::<u64>(self.height());
+    pending.keyword("/state").set_value::<u8>(state);
+
+    let stake = self.pending_stake(&key);
+    for alkane in context.incoming_alkanes.0.iter() {
+      stake.push(&alkane.id)?;
+    }
+
+    Ok(key)
+  }
+
+  // Returns false, leaving the roll pending, while its pool can't cover the full payout
+  fn settle_pending_roll(&self, context: &Context, key: &Vec<u8>) -> Result<bool> {
+    let pending = self.pending_roll_pointer(key);
+    let risk = pending.keyword("/risk").get_value::<u128>();
+    let tier = PrizeTier::from_u128(pending.keyword("/tier").get_value::<u8>() as u128)?;
+
+    // Replay the roll as if the owner had sent the escrowed stake
+    let escrow = self.pending_stake(key);
+    let stake = (0..escrow.count()).map(|index| escrow.get(index)).collect::<Result<Vec<AlkaneId>>>()?;
+    let roll_context = Context {
+      myself: context.myself,
+      caller: self.pending_owner(key)?,
+      incoming_alkanes: AlkaneTransferParcel(stake.iter().map(|id| AlkaneTransfer { id: *id, value: 1u128 }).collect()),
+      ..Default::default()
+    };
+
+    let prizes = (stake.len() as u128) * (self.max_stake_multiplier(&roll_context, risk)? - 1);
+    if self.available_prizes(tier)? < prizes {
+      return Ok(false);
+    }
+
+    escrow.drain()?;
+
+    let txid = Txid::from_slice(key)?;
+    let salt = pending.keyword("/salt").get_value::<u128>();
+    let (_, outcome, _) = self.draw_outcome(&roll_context, &txid, salt, risk, self.tier_handicap(tier), false, true)?;
+
+    let mut winnings = CallResponse::default();
+    match outcome {
+      RollOutcome::Lose => self.forfeit_stake(&roll_context)?,
+      RollOutcome::Push => winnings.alkanes = roll_context.incoming_alkanes.clone(),
+      RollOutcome::Win(multiplier) => {
+        winnings.alkanes = roll_context.incoming_alkanes.clone();
+        self.pay_prizes(&mut winnings, tier, (stake.len() as u128) * (multiplier - 1))?;
+        self.log_roll_prizes(&roll_context.caller, &txid, &winnings.alkanes.0[stake.len()..])?;
+      }
+    }
+
+    let payout = self.pending_payout(key);
+    for alkane in winnings.alkanes.0.iter() {
+      payout.push(&alkane.id)?;
+    }
+
+    // A lost roll has nothing left to claim
+    let state = if payout.count() == 0 { STATE_CLAIMED } else { STATE_RESOLVED };
+
+    pending.keyword("/multiplier").set_value::<u128>(outcome.multiplier());
+    pending.keyword("/state").set_value::<u8>(state);
+    self.settle_side_bets(key, Some(&outcome));
+
+    Ok(true)
+  }
+
+  // Once QUEUE_EXPIRY_BLOCKS have passed the stake moves to the payout, to be
+  // claimed like a push, so a roll the pools may never cover can't hold up the
+  // queue for good. Returns false while the roll can still wait.
+  fn expire_queued_roll(&self, key: &Vec<u8>) -> Result<bool> {
+    let pending = self.pending_roll_pointer(key);
+    if self.height() < pending.keyword("/height").get_value::<u64>() + QUEUE_EXPIRY_BLOCKS {
+      return Ok(false);
+    }
+
+    let payout = self.pending_payout(key);
+    for panda in self.pending_stake(key).drain()? {
+      payout.push(&panda)?;
+    }
+
+    pending.keyword("/multiplier").set_value::<u128>(RollOutcome::Push.multiplier());
+    pending.keyword("/state").set_value::<u8>(STATE_RESOLVED);
+
+    Ok(true)
+  }
+
+  fn roll_queue_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/roll-queue")
+  }
+
+  pub(crate) fn pending_roll_pointer(&self, key: &Vec<u8>) -> StoragePointer {
+    StoragePointer::from_keyword("/pending-rolls/").select(key)
+  }
+
+  fn pending_owner(&self, key: &Vec<u8>) -> Result<AlkaneId> {
+    let owner = self.pending_roll_pointer(key).keyword("/owner").get();
+    if owner.len() == 0 {
+      return Err(anyhow!("Unknown roll"));
+    }
+
+    alkane_id_from_bytes(&owner)
+  }
+
+  fn pending_stake(&self, key: &Vec<u8>) -> PandaStack {
+    PandaStack::at(&self.pending_roll_pointer(key).keyword("/stake"))
+  }
+
+  fn pending_payout(&self, key: &Vec<u8>) -> PandaStack {
+    PandaStack::at(&self.pending_roll_pointer(key).keyword("/payout"))
+  }
+}
+
+// Opcode inputs are u128s, so a txid arrives as its low and high 16 bytes
+pub(crate) fn txid_key(txid_lo: u128, txid_hi: u128) -> Vec<u8> {
+  let mut key = txid_lo.to_le_bytes().to_vec();
+  key.extend_from_slice(&txid_hi.to_le_bytes());
+  key
+}
@@ -0,0 +1,74 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::{sha256, Hash};
+
+use crate::{alkane_id_to_bytes, digest_value, PandaRoll};
+
+// Roll credits burned per practice roll
+const PRACTICE_FEE: u128 = 10;
+
+// Most recent practice results returned by GetPracticeHistory
+const PRACTICE_HISTORY_LIMIT: u128 = 20;
+
+impl PandaRoll {
+  // Same odds as a real roll, but the staked panda always comes back and nothing
+  // besides the credits and the caller's practice history is touched
+  pub(crate) fn practice_roll(&self, risk: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+
+    let remaining = self.burn_credits(&context, PRACTICE_FEE)?;
+
+    let pandas = remaining.0.iter().filter(|alkane| alkane.id != context.myself).count();
+    if pandas < 1 {
+      return Err(anyhow!("Must send at least 1 Panda to roll"));
+    }
+
+    for alkane in remaining.0.iter().filter(|alkane| alkane.id != context.myself) {
+      if !self.accepts_panda(&alkane.id)? {
+        return Err(anyhow!("Invalid Panda ID"));
+      }
+    }
+
+    // Drawn like a real roll but read-only, so the nonce and entropy seed stay put
+    let preimage = self.roll_preimage_at(&context.caller, &txid, 0, self.roll_nonce(&context.caller))?;
+    let value = digest_value(&sha256::Hash::hash(&preimage).to_byte_array());
+    let multiplier = self.entropy_outcome(&context, value, risk, 0)?.multiplier();
+
+    let mut history = self.practice_history_pointer(&context.caller);
+    let count = history.get_value::<u128>();
+    history.select(&count.to_le_bytes().to_vec()).set_value::<u128>(multiplier);
+    history.set_value::<u128>(count + 1);
+
+    let mut response = CallResponse::forward(&remaining);
+    response.data = multiplier.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Total practice rolls followed by up to the last 20 multipliers, oldest first
+  pub(crate) fn get_practice_history(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let history = self.practice_history_pointer(&context.caller);
+    let count = history.get_value::<u128>();
+
+    let mut data = count.to_le_bytes().to_vec();
+    for i in count.saturating_sub(PRACTICE_HISTORY_LIMIT)..count {
+      data.extend_from_slice(&history.select(&i.to_le_bytes().to_vec()).get_value::<u128>().to_le_bytes());
+    }
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  fn practice_history_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/practice/").select(&alkane_id_to_bytes(caller))
+  }
+}
@@ -0,0 +1,110 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::response::CallResponse;
+
+use anyhow::{anyhow, Result};
+
+use crate::PandaRoll;
+
+// A scheduled boosted-odds window of [start_height, end_height)
+struct Promo {
+  start_height: u128,
+  end_height: u128,
+  threshold: u8,
+}
+
+impl PandaRoll {
+  // While the promo runs, any entropy value at or above `threshold` wins
+  pub(crate) fn schedule_promo(&self, start_height: u128, end_height: u128, threshold: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    if start_height >= end_height {
+      return Err(anyhow!("Promo must end after it starts"));
+    }
+
+    let threshold = u8::try_from(threshold).map_err(|_| anyhow!("Invalid promo threshold"))?;
+
+    let promo_id = self.add_promo(start_height, end_height, threshold);
+    self.emit_config_change(&context);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = promo_id.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Start height, end height and threshold of the active promo, empty if none is running
+  pub(crate) fn get_active_promo(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    if let Some(promo) = self.active_promo() {
+      let mut data = Vec::with_capacity(48);
+      data.extend_from_slice(&promo.start_height.to_le_bytes());
+      data.extend_from_slice(&promo.end_height.to_le_bytes());
+      data.extend_from_slice(&(promo.threshold as u128).to_le_bytes());
+
+      response.data = data;
+    }
+
+    Ok(response)
+  }
+
+  // Win threshold of the most generous promo running at the current height
+  pub(crate) fn promo_threshold(&self) -> Option<u8> {
+    self.active_promo().map(|promo| promo.threshold)
+  }
+
+  fn active_promo(&self) -> Option<Promo> {
+    let height = self.height() as u128;
+
+    (0..self.promos_count())
+      .map(|promo_id| self.promo(promo_id))
+      .filter(|promo| promo.start_height <= height && height < promo.end_height)
+      .min_by_key(|promo| promo.threshold)
+  }
+
+  pub(crate) fn add_promo(&self, start_height: u128, end_height: u128, threshold: u8) -> u128 {
+    let promo_id = self.promos_count();
+    self.promos_pointer().set_value::<u128>(promo_id + 1);
+
+    let promo = self.promo_pointer(promo_id);
+    promo.keyword("/start").set_value::<u128>(start_height);
+    promo.keyword("/end").set_value::<u128>(end_height);
+    promo.keyword("/threshold").set_value::<u8>(threshold);
+
+    promo_id
+  }
+
+  // Every promo ever scheduled, as (start height, end height, threshold)
+  pub(crate) fn promos(&self) -> Vec<(u128, u128, u8)> {
+    (0..self.promos_count())
+      .map(|promo_id| self.promo(promo_id))
+      .map(|promo| (promo.start_height, promo.end_height, promo.threshold))
+      .collect()
+  }
+
+  fn promo(&self, promo_id: u128) -> Promo {
+    let promo = self.promo_pointer(promo_id);
+
+    Promo {
+      start_height: promo.keyword("/start").get_value::<u128>(),
+      end_height: promo.keyword("/end").get_value::<u128>(),
+      threshold: promo.keyword("/threshold").get_value::<u8>(),
+    }
+  }
+
+  fn promos_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/promos/")
+  }
+
+  fn promos_count(&self) -> u128 {
+    self.promos_pointer().get_value::<u128>()
+  }
+
+  fn promo_pointer(&self, promo_id: u128) -> StoragePointer {
+    self.promos_pointer().select(&promo_id.to_le_bytes().to_vec())
+  }
+}
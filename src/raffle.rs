@@ -0,0 +1,163 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, stack::PandaStack, PandaRoll, PrizeTier};
+
+// A raffle round closes this many blocks after its first ticket is issued
+const RAFFLE_PERIOD_BLOCKS: u64 = 1008;
+
+impl PandaRoll {
+  // Tickets are stored as issuance entries of (cumulative ticket count, owner) so
+  // a draw can binary search for the winning ticket instead of storing every one.
+  pub(crate) fn issue_raffle_tickets(&self, owner: &AlkaneId, tickets: u128) -> Result<()> {
+    let round = self.raffle_round_pointer();
+
+    if round.keyword("/deadline").get_value::<u64>() == 0 {
+      round.keyword("/deadline").set_value::<u64>(self.height() + RAFFLE_PERIOD_BLOCKS);
+    }
+
+    let total = round.keyword("/total").get_value::<u128>()
+      .checked_add(tickets)
+      .ok_or_else(|| anyhow!("raffle tickets overflow"))?;
+    round.keyword("/total").set_value::<u128>(total);
+
+    let entry_count = round.keyword("/entry-count").get_value::<u128>();
+    let mut entry = total.to_le_bytes().to_vec();
+    entry.extend(alkane_id_to_bytes(owner));
+    round.keyword("/entries/").select(&entry_count.to_le_bytes().to_vec()).set(Arc::new(entry));
+    round.keyword("/entry-count").set_value::<u128>(entry_count + 1);
+
+    let mut owner_tickets = round.keyword("/tickets/").select(&alkane_id_to_bytes(owner));
+    let owned = owner_tickets.get_value::<u128>();
+    owner_tickets.set_value::<u128>(owned.saturating_add(tickets));
+
+    Ok(())
+  }
+
+  pub(crate) fn draw_raffle(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let round = self.raffle_round_pointer();
+
+    let deadline = round.keyword("/deadline").get_value::<u64>();
+    if deadline == 0 || self.height() < deadline {
+      return Err(anyhow!("Raffle is not ready to be drawn"));
+    }
+
+    let total = round.keyword("/total").get_value::<u128>();
+    if total == 0 {
+      return Err(anyhow!("No raffle tickets issued"));
+    }
+
+    let block_hash = self.block_hash()?;
+    let winning_ticket = u128::from_le_bytes(block_hash[..16].try_into().unwrap()) % total;
+    let winner = self.raffle_ticket_owner(&round, winning_ticket)?;
+
+    // The prize is drawn like any other, and the winner collects it in their own call
+    self.ensure_prizes_available(PrizeTier::Common, 1)?;
+
+    let mut prize = CallResponse::default();
+    self.pay_prizes(&mut prize, PrizeTier::Common, 1)?;
+    for alkane in prize.alkanes.0.iter() {
+      self.raffle_prizes(&winner).push(&alkane.id)?;
+    }
+
+    let current = self.raffle_round();
+    StoragePointer::from_keyword("/raffle/round").set_value::<u128>(current + 1);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = alkane_id_to_bytes(&winner);
+
+    Ok(response)
+  }
+
+  pub(crate) fn claim_raffle_prizes(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let prizes = self.raffle_prizes(&context.caller).drain()?;
+
+    if prizes.len() == 0 {
+      return Err(anyhow!("No raffle prizes to claim"));
+    }
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    for prize in prizes {
+      response.alkanes.0.push(AlkaneTransfer {
+        id: prize,
+        value: 1u128,
+      });
+    }
+
+    Ok(response)
+  }
+
+  pub(crate) fn get_my_tickets(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let tickets = self.raffle_round_pointer()
+      .keyword("/tickets/")
+      .select(&alkane_id_to_bytes(&context.caller))
+      .get_value::<u128>();
+
+    response.data = tickets.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn get_raffle_info(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    let round = self.raffle_round_pointer();
+
+    let mut data = Vec::with_capacity(48);
+    data.extend_from_slice(&self.raffle_round().to_le_bytes());
+    data.extend_from_slice(&(round.keyword("/deadline").get_value::<u64>() as u128).to_le_bytes());
+    data.extend_from_slice(&round.keyword("/total").get_value::<u128>().to_le_bytes());
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  fn raffle_ticket_owner(&self, round: &StoragePointer, ticket: u128) -> Result<AlkaneId> {
+    let entries = round.keyword("/entries/");
+    let entry_count = round.keyword("/entry-count").get_value::<u128>();
+
+    // First entry whose cumulative total is past the winning ticket
+    let (mut low, mut high) = (0u128, entry_count);
+    while low < high {
+      let mid = low + (high - low) / 2;
+      let entry = entries.select(&mid.to_le_bytes().to_vec()).get();
+      let end = u128::from_le_bytes(entry[..16].try_into().unwrap());
+
+      if end > ticket {
+        high = mid;
+      } else {
+        low = mid + 1;
+      }
+    }
+
+    let entry = entries.select(&low.to_le_bytes().to_vec()).get();
+    if entry.len() != 48 {
+      return Err(anyhow!("Invalid raffle entry"));
+    }
+
+    alkane_id_from_bytes(&entry[16..])
+  }
+
+  fn raffle_round(&self) -> u128 {
+    StoragePointer::from_keyword("/raffle/round").get_value::<u128>()
+  }
+
+  fn raffle_round_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/raffle/rounds/").select(&self.raffle_round().to_le_bytes().to_vec())
+  }
+
+  fn raffle_prizes(&self, owner: &AlkaneId) -> PandaStack {
+    PandaStack::at(&StoragePointer::from_keyword("/raffle/prizes/").select(&alkane_id_to_bytes(owner)))
+  }
+}
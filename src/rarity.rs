@@ -0,0 +1,166 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{context::Context, id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+
+use crate::{PandaRoll, RollOutcome, PANDA_BLOCK};
+
+// Per-panda attributes set by the house: a rarity tier and trait flags.
+// 0 = common up to 3 = legendary
+const MAX_RARITY: u8 = 3;
+
+// Entropy points added to a roll for each rarity tier of the staked pandas
+const RARITY_BONUS_PER_TIER: u8 = 4;
+
+// Relative chance of each rarity tier being picked as a prize, so a legendary
+// panda is 8 times harder to win than a common one
+const PRIZE_WEIGHTS: [u128; 4] = [8, 4, 2, 1];
+
+// Trait flags, stored as a bitmask per panda
+pub(crate) const TRAIT_GOLDEN: u8 = 0b01;
+pub(crate) const TRAIT_ZOMBIE: u8 = 0b10;
+
+// Golden stakes win one extra multiple at or above this entropy value
+const GOLDEN_BAND: u8 = 240;
+// Zombie stakes come back from a loss below this entropy value
+const ZOMBIE_BAND: u8 = 8;
+
+impl PandaRoll {
+  pub(crate) fn set_rarity(&self, tx: u128, tier: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    if tier > MAX_RARITY as u128 {
+      return Err(anyhow!("Invalid rarity tier"));
+    }
+
+    rarity_pointer(tx).set_value::<u8>(tier as u8);
+    self.reweigh_prize(&AlkaneId { block: PANDA_BLOCK, tx })?;
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn get_rarity(&self, tx: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = vec![self.rarity(tx)];
+
+    Ok(response)
+  }
+
+  pub(crate) fn rarity(&self, tx: u128) -> u8 {
+    rarity_pointer(tx).get_value::<u8>()
+  }
+
+  pub(crate) fn traits(&self, tx: u128) -> u8 {
+    self.traits_pointer(tx).get_value::<u8>()
+  }
+
+  // Writes both attributes back from a snapshot, checked as the setters check them
+  pub(crate) fn restore_attributes(&self, tx: u128, rarity: u8, traits: u8) -> Result<()> {
+    if rarity > MAX_RARITY || traits > TRAIT_GOLDEN | TRAIT_ZOMBIE {
+      return Err(anyhow!("Invalid panda attributes"));
+    }
+
+    rarity_pointer(tx).set_value::<u8>(rarity);
+    self.traits_pointer(tx).set_value::<u8>(traits);
+
+    Ok(())
+  }
+
+  // A multi-panda stake only gets the bonus of its most common panda
+  pub(crate) fn rarity_bonus(&self, context: &Context) -> u8 {
+    let tier = context.incoming_alkanes.0.iter()
+      .filter(|alkane| alkane.id != context.myself)
+      .map(|alkane| self.panda_rarity(&alkane.id))
+      .min()
+      .unwrap_or(0);
+
+    tier * RARITY_BONUS_PER_TIER
+  }
+
+  pub(crate) fn set_traits(&self, tx: u128, traits: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    if traits > (TRAIT_GOLDEN | TRAIT_ZOMBIE) as u128 {
+      return Err(anyhow!("Unknown trait flags"));
+    }
+
+    self.traits_pointer(tx).set_value::<u8>(traits as u8);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn get_traits(&self, tx: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = vec![self.traits(tx)];
+
+    Ok(response)
+  }
+
+  // Only traits shared by every staked panda count
+  pub(crate) fn stake_traits(&self, context: &Context) -> u8 {
+    let mut pandas = context.incoming_alkanes.0.iter()
+      .filter(|alkane| alkane.id != context.myself)
+      .peekable();
+
+    if pandas.peek().is_none() {
+      return 0;
+    }
+
+    pandas.fold(u8::MAX, |traits, alkane| traits & self.panda_traits(&alkane.id))
+  }
+
+  pub(crate) fn apply_traits(&self, outcome: RollOutcome, value: u8, traits: u8, risk: u128) -> Result<RollOutcome> {
+    Ok(match outcome {
+      RollOutcome::Win(multiplier) if traits & TRAIT_GOLDEN != 0 && value >= GOLDEN_BAND => {
+        RollOutcome::Win(multiplier + 1)
+      }
+      RollOutcome::Lose if traits & TRAIT_ZOMBIE != 0 && value < ZOMBIE_BAND => {
+        RollOutcome::Win(self.min_multiplier(risk)?)
+      }
+      outcome => outcome,
+    })
+  }
+
+  fn panda_traits(&self, id: &AlkaneId) -> u8 {
+    if id.block != PANDA_BLOCK {
+      return 0;
+    }
+
+    self.traits(id.tx)
+  }
+
+  fn traits_pointer(&self, tx: u128) -> StoragePointer {
+    StoragePointer::from_keyword("/traits/").select(&tx.to_le_bytes().to_vec())
+  }
+
+  fn panda_rarity(&self, id: &AlkaneId) -> u8 {
+    panda_rarity(id)
+  }
+}
+
+fn panda_rarity(id: &AlkaneId) -> u8 {
+  if id.block != PANDA_BLOCK {
+    return 0;
+  }
+
+  rarity_pointer(id.tx).get_value::<u8>()
+}
+
+// The weight prize pools give a panda when it is drawn as a prize
+pub(crate) fn prize_weight(id: &AlkaneId) -> u128 {
+  PRIZE_WEIGHTS[panda_rarity(id).min(MAX_RARITY) as usize]
+}
+
+fn rarity_pointer(tx: u128) -> StoragePointer {
+  StoragePointer::from_keyword("/rarity/").select(&tx.to_le_bytes().to_vec())
+}
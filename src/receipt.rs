@@ -0,0 +1,58 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::response::CallResponse;
+
+use anyhow::Result;
+use bitcoin::hashes::Hash;
+use bitcoin::Txid;
+
+use crate::{PandaRoll, RollOutcome};
+
+impl PandaRoll {
+  // Roll receipts are deployed from this template. A template of 0, the
+  // default, stops minting them.
+  pub(crate) fn set_receipt_template(&self, tx: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.receipt_template_pointer().set_value::<u128>(tx);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // Deploys a receipt for a roll settled in this transaction and hands it to the
+  // roller, initialised with its number, the txid as its low and high 16 bytes
+  // and the outcome multiplier: 0 for a loss, 1 for a push
+  pub(crate) fn mint_receipt(&self, response: &mut CallResponse, txid: &Txid, outcome: &RollOutcome) -> Result<()> {
+    let template = self.receipt_template_pointer().get_value::<u128>();
+    if template == 0 {
+      return Ok(());
+    }
+
+    let receipt_number = self.receipts_pointer().get_value::<u128>();
+    self.receipts_pointer().set_value::<u128>(receipt_number + 1);
+
+    let txid = txid.as_byte_array();
+    let minted = self.spawn_from_template(template, vec![
+      0,
+      receipt_number,
+      u128::from_le_bytes(txid[..16].try_into()?),
+      u128::from_le_bytes(txid[16..].try_into()?),
+      outcome.multiplier(),
+    ])?;
+
+    response.alkanes.0.extend(minted.0);
+
+    Ok(())
+  }
+
+  pub(crate) fn receipt_template_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/receipt-template")
+  }
+
+  fn receipts_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/receipts")
+  }
+}
@@ -0,0 +1,134 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{context::Context, id::AlkaneId, parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use bitcoin::Txid;
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, PandaRoll};
+
+// Entropy rounds reserved for re-roll draws, well clear of parlay and tournament rounds
+pub(crate) const REROLL_ROUND: u128 = u128::MAX;
+const REROLL_DROP_ROUND: u128 = u128::MAX - 1;
+
+// Out of 256, the chance a losing roll drops a re-roll token
+const REROLL_DROP_CHANCE: u8 = 16;
+
+// Re-roll tokens are an alkane the house stocks up on. Losses hand them out
+// from the inventory and spent ones go back into it.
+impl PandaRoll {
+  pub(crate) fn set_reroll_token(&self, block: u128, tx: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    // The inventory is denominated in the re-roll token
+    if self.reroll_inventory() != 0 {
+      return Err(anyhow!("Re-roll inventory must be empty to change the token"));
+    }
+
+    self.reroll_token_pointer().set(Arc::new(alkane_id_to_bytes(&AlkaneId { block, tx })));
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn fund_rerolls(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let token = self.reroll_token()?
+      .ok_or_else(|| anyhow!("Re-roll token is not configured"))?;
+
+    let amount = self.incoming_amount(&context, &token);
+    if amount == 0 {
+      return Err(anyhow!("Must send re-roll tokens"));
+    }
+
+    let remaining = self.take_incoming(&context, &token, amount)?;
+    self.add_reroll_inventory(amount)?;
+
+    Ok(CallResponse::forward(&remaining))
+  }
+
+  // Pulls one re-roll token out of the incoming alkanes so the rest can be staked
+  pub(crate) fn take_reroll(&self, mut context: Context) -> Result<(Context, bool)> {
+    let token = match self.reroll_token()? {
+      Some(token) if self.incoming_amount(&context, &token) > 0 => token,
+      _ => return Ok((context, false)),
+    };
+
+    context.incoming_alkanes = self.take_incoming(&context, &token, 1)?;
+
+    Ok((context, true))
+  }
+
+  // Hands back an unused re-roll token, or keeps a spent one
+  pub(crate) fn settle_reroll(&self, response: &mut CallResponse, rerolled: bool) -> Result<()> {
+    if rerolled {
+      return self.add_reroll_inventory(1);
+    }
+
+    if let Some(token) = self.reroll_token()? {
+      response.alkanes.0.push(AlkaneTransfer {
+        id: token,
+        value: 1u128,
+      });
+    }
+
+    Ok(())
+  }
+
+  pub(crate) fn drop_reroll(&self, response: &mut CallResponse, txid: &Txid) -> Result<()> {
+    let inventory = self.reroll_inventory();
+    if inventory == 0 {
+      return Ok(());
+    }
+
+    let token = match self.reroll_token()? {
+      Some(token) => token,
+      None => return Ok(()),
+    };
+
+    let seed = self.calculate_seed(txid)?;
+    if self.calculate_round_value(&seed, REROLL_DROP_ROUND) >= REROLL_DROP_CHANCE {
+      return Ok(());
+    }
+
+    self.reroll_inventory_pointer().set_value::<u128>(inventory - 1);
+    response.alkanes.0.push(AlkaneTransfer {
+      id: token,
+      value: 1u128,
+    });
+
+    Ok(())
+  }
+
+  pub(crate) fn reroll_token_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/reroll-token")
+  }
+
+  fn reroll_token(&self) -> Result<Option<AlkaneId>> {
+    let bytes = self.reroll_token_pointer().get();
+    if bytes.len() == 0 {
+      return Ok(None);
+    }
+
+    Ok(Some(alkane_id_from_bytes(&bytes)?))
+  }
+
+  fn reroll_inventory_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/rerolls/inventory")
+  }
+
+  fn reroll_inventory(&self) -> u128 {
+    self.reroll_inventory_pointer().get_value::<u128>()
+  }
+
+  fn add_reroll_inventory(&self, amount: u128) -> Result<()> {
+    let inventory = self.reroll_inventory().checked_add(amount)
+      .ok_or_else(|| anyhow!("re-roll inventory overflow"))?;
+    self.reroll_inventory_pointer().set_value::<u128>(inventory);
+
+    Ok(())
+  }
+}
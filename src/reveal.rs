@@ -0,0 +1,64 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::{sha256, Hash};
+use std::sync::Arc;
+
+use crate::{alkane_id_to_bytes, PandaRoll};
+
+// Blocks a seed commitment stays revealable
+const SEED_COMMIT_EXPIRY_BLOCKS: u64 = 144;
+
+// Commit-reveal rolls: the player commits to sha256 of a secret u128 (16 bytes
+// LE), then reveals it with their stake in a later block. The secret becomes the
+// roll's salt, so neither the player nor the miner of the reveal block picks the
+// outcome alone.
+impl PandaRoll {
+  pub(crate) fn commit_seed(&self, hash_lo: u128, hash_hi: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let mut hash = hash_lo.to_le_bytes().to_vec();
+    hash.extend_from_slice(&hash_hi.to_le_bytes());
+
+    let commitment = self.seed_commitment_pointer(&context.caller);
+    commitment.keyword("/hash").set(Arc::new(hash));
+    commitment.keyword("/height").set_value::<u64>(self.height());
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn reveal_and_roll(&self, preimage: u128, risk: u128, prize_tier: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let commitment = self.seed_commitment_pointer(&context.caller);
+    let hash = commitment.keyword("/hash").get();
+    if hash.len() == 0 {
+      return Err(anyhow!("No seed committed"));
+    }
+
+    let height = commitment.keyword("/height").get_value::<u64>();
+    if self.height() <= height {
+      return Err(anyhow!("Seed must be revealed in a later block"));
+    }
+
+    if self.height() >= height + SEED_COMMIT_EXPIRY_BLOCKS {
+      return Err(anyhow!("Seed commitment has expired"));
+    }
+
+    if sha256::Hash::hash(&preimage.to_le_bytes()).to_byte_array().to_vec() != *hash {
+      return Err(anyhow!("Preimage does not match the committed seed"));
+    }
+
+    // Each commitment is good for one roll
+    commitment.keyword("/hash").set(Arc::new(Vec::new()));
+
+    self.roll(risk, prize_tier, preimage)
+  }
+
+  fn seed_commitment_pointer(&self, caller: &AlkaneId) -> StoragePointer {
+    StoragePointer::from_keyword("/seed-commits/").select(&alkane_id_to_bytes(caller))
+  }
+}
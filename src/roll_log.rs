@@ -0,0 +1,129 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{context::Context, id::AlkaneId, parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::Hash;
+use bitcoin::Txid;
+use std::sync::Arc;
+
+use crate::{alkane_id_to_bytes, PandaRoll, RollOutcome};
+
+// An append-only record of every settled roll. The count lives at `/rolls/` and
+// roll `n` lives at `/rolls/` ++ n, so entries are never rewritten once added,
+// apart from the prizes a win pays out. Each txid points at its latest roll.
+impl PandaRoll {
+  // `threshold` is the lowest winning value for the roll before any bonus. The
+  // fairness proof goes into the same entry with `log_roll`. Returns the roll's
+  // sequence number.
+  pub(crate) fn append_roll(&self, context: &Context, txid: &Txid, threshold: u8, value: u8, outcome: &RollOutcome) -> Result<u128> {
+    let mut rolls = self.rolls_pointer();
+    let sequence = rolls.get_value::<u128>();
+
+    let stake = context.incoming_alkanes.0.iter()
+      .filter(|alkane| alkane.id != context.myself)
+      .flat_map(|alkane| alkane_id_to_bytes(&alkane.id))
+      .collect::<Vec<u8>>();
+
+    let entry = self.roll_entry_pointer(sequence);
+    entry.keyword("/height").set_value::<u64>(self.height());
+    entry.keyword("/txid").set(Arc::new(txid.as_byte_array().to_vec()));
+    entry.keyword("/threshold").set_value::<u8>(threshold);
+    entry.keyword("/value").set_value::<u8>(value);
+    entry.keyword("/outcome").set_value::<u128>(outcome.multiplier());
+    entry.keyword("/stake").set(Arc::new(stake));
+
+    self.roll_by_txid_pointer(&txid.as_byte_array().to_vec()).set_value::<u128>(sequence + 1);
+    rolls.set_value::<u128>(sequence + 1);
+    self.emit_roll(&context.caller, sequence, outcome);
+
+    Ok(sequence)
+  }
+
+  // Attaches the pandas a win paid out to the transaction's latest roll, and
+  // counts them towards the caller's winnings
+  pub(crate) fn log_roll_prizes(&self, caller: &AlkaneId, txid: &Txid, prizes: &[AlkaneTransfer]) -> Result<()> {
+    self.add_player_pandas(caller, prizes.len() as u128, 0)?;
+    self.emit_win(caller, prizes.len() as u128);
+
+    let position = self.roll_by_txid_pointer(&txid.as_byte_array().to_vec()).get_value::<u128>();
+    if position == 0 {
+      return Ok(());
+    }
+
+    let prizes = prizes.iter().flat_map(|alkane| alkane_id_to_bytes(&alkane.id)).collect::<Vec<u8>>();
+    self.roll_entry_pointer(position - 1).keyword("/prizes").set(Arc::new(prizes));
+
+    Ok(())
+  }
+
+  // Sequence number u128, height u64, outcome multiplier u128, entropy value u8,
+  // threshold u8, then the staked and the prize pandas, each as a u128 count
+  // followed by packed 32 byte ids
+  pub(crate) fn get_roll_by_txid(&self, txid_lo: u128, txid_hi: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let mut key = txid_lo.to_le_bytes().to_vec();
+    key.extend_from_slice(&txid_hi.to_le_bytes());
+
+    let position = self.roll_by_txid_pointer(&key).get_value::<u128>();
+    if position == 0 {
+      return Err(anyhow!("No roll recorded for that txid"));
+    }
+
+    response.data = self.roll_record(position - 1);
+
+    Ok(response)
+  }
+
+  // The most recent roll's txid followed by the same record GetRollByTxid returns
+  pub(crate) fn get_last_roll_result(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let sequence = self.rolls_count().checked_sub(1)
+      .ok_or_else(|| anyhow!("No rolls recorded yet"))?;
+
+    let mut data = self.roll_entry_pointer(sequence).keyword("/txid").get().to_vec();
+    data.extend(self.roll_record(sequence));
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  fn roll_record(&self, sequence: u128) -> Vec<u8> {
+    let entry = self.roll_entry_pointer(sequence);
+
+    let mut data = sequence.to_le_bytes().to_vec();
+    data.extend_from_slice(&entry.keyword("/height").get_value::<u64>().to_le_bytes());
+    data.extend_from_slice(&entry.keyword("/outcome").get_value::<u128>().to_le_bytes());
+    data.push(entry.keyword("/value").get_value::<u8>());
+    data.push(entry.keyword("/threshold").get_value::<u8>());
+
+    for ids in [entry.keyword("/stake").get(), entry.keyword("/prizes").get()] {
+      data.extend_from_slice(&(ids.len() as u128 / 32).to_le_bytes());
+      data.extend_from_slice(&ids);
+    }
+
+    data
+  }
+
+  pub(crate) fn rolls_count(&self) -> u128 {
+    self.rolls_pointer().get_value::<u128>()
+  }
+
+  pub(crate) fn roll_entry_pointer(&self, sequence: u128) -> StoragePointer {
+    self.rolls_pointer().select(&sequence.to_le_bytes().to_vec())
+  }
+
+  pub(crate) fn roll_by_txid_pointer(&self, key: &Vec<u8>) -> StoragePointer {
+    StoragePointer::from_keyword("/rolls/by-txid/").select(key)
+  }
+
+  fn rolls_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/rolls/")
+  }
+}
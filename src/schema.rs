@@ -0,0 +1,139 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::response::CallResponse;
+
+use anyhow::{anyhow, Result};
+
+use crate::{stack::PandaStack, PandaRoll, PrizeTier};
+
+// Storage layout version written at Initialize. Deployments from before it was
+// recorded read as 0.
+//
+// 1: prize pools index each panda's position
+// 2: prize pools keep a Merkle tree over their slots
+// 3: prize pools keep each panda's prize weight in a Fenwick tree, and size their
+//    Merkle tree to the pool
+pub(crate) const SCHEMA_VERSION: u128 = 3;
+
+// Most stack entries a single Migrate call may rewrite, to stay within fuel
+const MAX_MIGRATION_BATCH: u128 = 500;
+
+impl PandaRoll {
+  // Moves the storage layout on by up to `limit` entries and returns the schema
+  // version once the call is done. Call again until it reports SCHEMA_VERSION.
+  pub(crate) fn migrate(&self, limit: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    if limit > MAX_MIGRATION_BATCH {
+      return Err(anyhow!("Too many entries in one batch"));
+    }
+
+    match self.schema_version() {
+      0 => self.index_positions(limit)?,
+      1 => self.build_merkle_trees(limit)?,
+      2 => self.build_weight_trees(limit)?,
+      _ => {}
+    }
+    self.emit_config_change(&context);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = self.schema_version().to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn set_schema_version(&self, version: u128) {
+    self.schema_version_pointer().set_value::<u128>(version);
+  }
+
+  pub(crate) fn schema_version(&self) -> u128 {
+    self.schema_version_pointer().get_value::<u128>()
+  }
+
+  // 0 -> 1: moves the pandas left in the single stack the contract started with
+  // into their prize pools, then builds the position index for both prize pools
+  // and the jackpot
+  fn index_positions(&self, limit: u128) -> Result<()> {
+    // Popping empties each old slot and brings the old count down with it
+    let legacy = PandaStack::new("/instances");
+    let moved = legacy.count().min(limit);
+    self.observe_markets();
+    for _ in 0..moved {
+      let id = legacy.pop()?;
+      self.pool_for(&id).push(&id)?;
+    }
+
+    if legacy.count() > 0 {
+      return Ok(());
+    }
+
+    let stacks = [self.pool(PrizeTier::Common), self.pool(PrizeTier::Rare), self.jackpot()];
+
+    if self.walk_stacks(&self.migration_pointer(), &stacks, limit - moved, PandaStack::reindex)? {
+      self.set_schema_version(1);
+    }
+
+    Ok(())
+  }
+
+  // 1 -> 2: hashes every prize pool entry into its pool's Merkle tree
+  fn build_merkle_trees(&self, limit: u128) -> Result<()> {
+    let stacks = [self.pool(PrizeTier::Common), self.pool(PrizeTier::Rare)];
+
+    if self.walk_stacks(&self.migration_pointer().keyword("/merkle"), &stacks, limit, PandaStack::rehash)? {
+      self.set_schema_version(2);
+    }
+
+    Ok(())
+  }
+
+  // 2 -> 3: weighs every prize pool entry into its pool's weight tree, then
+  // brings each pool's Merkle tree down from the old fixed depth
+  fn build_weight_trees(&self, limit: u128) -> Result<()> {
+    let stacks = [self.pool(PrizeTier::Common), self.pool(PrizeTier::Rare)];
+
+    if self.walk_stacks(&self.migration_pointer().keyword("/weights"), &stacks, limit, PandaStack::reweigh_range)? {
+      for stack in stacks.iter() {
+        stack.fit_merkle_tree()?;
+      }
+      self.set_schema_version(3);
+    }
+
+    Ok(())
+  }
+
+  // Runs `step` over up to `limit` entries, one stack after another, picking up
+  // where the last call left off at `migration`. Returns true once every stack
+  // is done.
+  fn walk_stacks(&self, migration: &StoragePointer, stacks: &[PandaStack], limit: u128, step: fn(&PandaStack, u128, u128) -> Result<u128>) -> Result<bool> {
+    let mut stack = migration.keyword("/stack");
+    let mut entry = migration.keyword("/entry");
+
+    let mut remaining = limit;
+    while let Some(current) = usize::try_from(stack.get_value::<u128>()).ok().and_then(|index| stacks.get(index)) {
+      let start = entry.get_value::<u128>();
+      let next = step(current, start, remaining)?;
+      remaining -= next - start;
+
+      if next < current.count() {
+        entry.set_value::<u128>(next);
+        return Ok(false);
+      }
+
+      stack.set_value::<u128>(stack.get_value::<u128>() + 1);
+      entry.set_value::<u128>(0);
+    }
+
+    Ok(true)
+  }
+
+  fn migration_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/schema/migration")
+  }
+
+  fn schema_version_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/schema-version")
+  }
+}
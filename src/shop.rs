@@ -0,0 +1,318 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, PandaRoll, PrizeTier};
+
+// The house buys pandas back at this share of the curve price, in basis points
+const SELL_SPREAD_BPS: u128 = 9_000;
+
+#[derive(Clone, Copy)]
+enum GachaPrize {
+  Nothing,
+  Consolation,
+  Common,
+  Rare,
+}
+
+// Entropy bands for a gacha pull, out of 256
+const GACHA_WEIGHTS: &[(GachaPrize, u16)] = &[
+  (GachaPrize::Nothing, 128),
+  (GachaPrize::Consolation, 80),
+  (GachaPrize::Common, 40),
+  (GachaPrize::Rare, 8),
+];
+
+// Roll credits paid by a consolation pull
+const GACHA_CONSOLATION_CREDITS: u128 = 50;
+
+// Trading pandas with the house for the configured payment alkane
+impl PandaRoll {
+  pub(crate) fn buy_mystery_box(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let price = self.mystery_box_price();
+    if price == 0 {
+      return Err(anyhow!("Mystery boxes are not for sale"));
+    }
+
+    let remaining = self.take_incoming(&context, &self.payment_token()?, price)?;
+    self.add_to_treasury(price)?;
+
+    // The box holds a prize drawn from the common pool like any other
+    self.ensure_prizes_available(PrizeTier::Common, 1)?;
+
+    let mut response = CallResponse::forward(&remaining);
+    self.pay_prizes(&mut response, PrizeTier::Common, 1)?;
+
+    Ok(response)
+  }
+
+  pub(crate) fn gacha_pull(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+
+    let price = self.gacha_price_pointer().get_value::<u128>();
+    if price == 0 {
+      return Err(anyhow!("Gacha pulls are not available"));
+    }
+
+    let remaining = self.take_incoming(&context, &self.payment_token()?, price)?;
+    self.add_to_treasury(price)?;
+
+    let value = self.calculate_random_value(&txid)? as u16;
+    let mut band_end = 0u16;
+    let mut prize = GachaPrize::Nothing;
+    for (category, weight) in GACHA_WEIGHTS {
+      band_end += weight;
+      if value < band_end {
+        prize = *category;
+        break;
+      }
+    }
+
+    // A panda prize falls back a category when its pool is empty
+    if let GachaPrize::Rare = prize {
+      if self.available_prizes(PrizeTier::Rare)? == 0 {
+        prize = GachaPrize::Common;
+      }
+    }
+    if let GachaPrize::Common = prize {
+      if self.available_prizes(PrizeTier::Common)? == 0 {
+        prize = GachaPrize::Consolation;
+      }
+    }
+
+    let mut response = CallResponse::forward(&remaining);
+    response.data = vec![prize as u8];
+
+    match prize {
+      GachaPrize::Nothing => {}
+      GachaPrize::Consolation => response.alkanes.0.push(self.mint_credits(&context, GACHA_CONSOLATION_CREDITS)?),
+      GachaPrize::Common => self.pay_prizes(&mut response, PrizeTier::Common, 1)?,
+      GachaPrize::Rare => self.pay_prizes(&mut response, PrizeTier::Rare, 1)?,
+    }
+
+    Ok(response)
+  }
+
+  pub(crate) fn buy_panda(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let price = self.panda_price()?;
+    let remaining = self.take_incoming(&context, &self.payment_token()?, price)?;
+    self.add_to_treasury(price)?;
+
+    let mut response = CallResponse::forward(&remaining);
+    response.data = price.to_le_bytes().to_vec();
+    self.pay_prizes(&mut response, PrizeTier::Common, 1)?;
+
+    Ok(response)
+  }
+
+  pub(crate) fn get_panda_price(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.panda_price()?.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // The house buys a panda into the stack, paying out of the treasury
+  pub(crate) fn sell_panda(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let panda = self.single_panda(&context.incoming_alkanes.0)?;
+
+    let quote = self.sell_quote()?;
+    let balance = self.treasury();
+    if balance < quote {
+      return Err(anyhow!("Treasury cannot cover the sale"));
+    }
+
+    self.treasury_pointer().set_value::<u128>(balance - quote);
+    self.observe_markets();
+    self.pool_for(&panda).push(&panda)?;
+
+    let mut response = CallResponse::default();
+    response.data = quote.to_le_bytes().to_vec();
+    response.alkanes.0.push(AlkaneTransfer {
+      id: self.payment_token()?,
+      value: quote,
+    });
+
+    Ok(response)
+  }
+
+  pub(crate) fn get_sell_quote(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.sell_quote()?.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn fund_treasury(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let payment_token = self.payment_token()?;
+
+    let amount = self.incoming_amount(&context, &payment_token);
+    if amount == 0 {
+      return Err(anyhow!("Must send the payment token"));
+    }
+
+    let remaining = self.take_incoming(&context, &payment_token, amount)?;
+    self.add_to_treasury(amount)?;
+
+    Ok(CallResponse::forward(&remaining))
+  }
+
+  pub(crate) fn claim_treasury(&self) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    let balance = self.treasury();
+    if balance == 0 {
+      return Err(anyhow!("Treasury is empty"));
+    }
+
+    self.treasury_pointer().set_value::<u128>(0);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.alkanes.0.push(AlkaneTransfer {
+      id: self.payment_token()?,
+      value: balance,
+    });
+
+    Ok(response)
+  }
+
+  // Linear curve: `base` while the stack holds at least `depth` pandas, rising by
+  // `slope` for every panda below that
+  pub(crate) fn set_panda_curve(&self, base: u128, slope: u128, depth: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    let curve = self.panda_curve_pointer();
+    curve.keyword("/base").set_value::<u128>(base);
+    curve.keyword("/slope").set_value::<u128>(slope);
+    curve.keyword("/depth").set_value::<u128>(depth);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn set_payment_token(&self, block: u128, tx: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    // The treasury is denominated in the payment token
+    if self.treasury() != 0 {
+      return Err(anyhow!("Claim the treasury before changing the payment token"));
+    }
+
+    self.payment_token_pointer().set(Arc::new(alkane_id_to_bytes(&AlkaneId { block, tx })));
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn set_mystery_box_price(&self, price: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.mystery_box_price_pointer().set_value::<u128>(price);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn set_gacha_price(&self, price: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.gacha_price_pointer().set_value::<u128>(price);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn payment_token_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/payment-token")
+  }
+
+  pub(crate) fn payment_token(&self) -> Result<AlkaneId> {
+    let bytes = self.payment_token_pointer().get();
+    if bytes.len() == 0 {
+      return Err(anyhow!("Payment token is not configured"));
+    }
+
+    alkane_id_from_bytes(&bytes)
+  }
+
+  pub(crate) fn panda_curve_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/panda-curve")
+  }
+
+  fn panda_price(&self) -> Result<u128> {
+    self.panda_price_at(self.pool(PrizeTier::Common).count())
+  }
+
+  // Quoted at the curve price once the sold panda is in the stack, less the spread
+  fn sell_quote(&self) -> Result<u128> {
+    let price = self.panda_price_at(self.pool(PrizeTier::Common).count().saturating_add(1))?;
+
+    price.checked_mul(SELL_SPREAD_BPS)
+      .map(|scaled| scaled / 10_000)
+      .ok_or_else(|| anyhow!("sell quote overflow"))
+  }
+
+  fn panda_price_at(&self, count: u128) -> Result<u128> {
+    let curve = self.panda_curve_pointer();
+    let base = curve.keyword("/base").get_value::<u128>();
+    if base == 0 {
+      return Err(anyhow!("Pandas are not for sale"));
+    }
+
+    let shortfall = curve.keyword("/depth").get_value::<u128>()
+      .saturating_sub(count);
+
+    curve.keyword("/slope").get_value::<u128>()
+      .checked_mul(shortfall)
+      .and_then(|premium| premium.checked_add(base))
+      .ok_or_else(|| anyhow!("panda price overflow"))
+  }
+
+  fn treasury_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/treasury")
+  }
+
+  fn treasury(&self) -> u128 {
+    self.treasury_pointer().get_value::<u128>()
+  }
+
+  fn add_to_treasury(&self, amount: u128) -> Result<()> {
+    let balance = self.treasury().checked_add(amount)
+      .ok_or_else(|| anyhow!("treasury overflow"))?;
+    self.treasury_pointer().set_value::<u128>(balance);
+
+    Ok(())
+  }
+
+  pub(crate) fn mystery_box_price_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/mystery-box-price")
+  }
+
+  fn mystery_box_price(&self) -> u128 {
+    self.mystery_box_price_pointer().get_value::<u128>()
+  }
+
+  pub(crate) fn gacha_price_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/gacha-price")
+  }
+}
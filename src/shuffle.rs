@@ -0,0 +1,81 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::response::CallResponse;
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{seeded_index, PandaRoll, PrizeTier};
+
+// Most swaps a single ShuffleStack call makes, to stay within fuel
+const MAX_SHUFFLE_BATCH: u128 = 200;
+
+const SHUFFLE_TIERS: [PrizeTier; 2] = [PrizeTier::Common, PrizeTier::Rare];
+
+impl PandaRoll {
+  // Anyone can reorder both prize pools with a Fisher-Yates shuffle seeded by the
+  // hash of the block the pass started in, which anyone can replay to verify. A
+  // pass runs over as many calls as it takes at MAX_SHUFFLE_BATCH swaps each,
+  // and a new one can start once per block after the last has finished. Returns
+  // 1 once the pass is done, 0 while it still has swaps to make.
+  pub(crate) fn shuffle_stack(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let shuffle = self.shuffle_pointer();
+    let mut tier = shuffle.keyword("/tier");
+    let mut cursor = shuffle.keyword("/cursor");
+
+    // No pass is running before the first one or once the tier has moved past
+    // the last
+    if tier.get_value::<u8>() as usize >= SHUFFLE_TIERS.len() || shuffle.keyword("/seed").get().len() == 0 {
+      let height = self.height();
+      let mut last_height = shuffle.keyword("/last-height");
+      if last_height.get_value::<u64>() == height {
+        return Err(anyhow!("Stack already shuffled this block"));
+      }
+      last_height.set_value::<u64>(height);
+
+      shuffle.keyword("/seed").set(Arc::new(self.block_hash()?));
+      tier.set_value::<u8>(0);
+      cursor.set_value::<u128>(self.pool(SHUFFLE_TIERS[0]).count());
+    }
+
+    let block_hash = shuffle.keyword("/seed").get().to_vec();
+    let mut remaining = MAX_SHUFFLE_BATCH;
+
+    while let Some(current) = SHUFFLE_TIERS.get(tier.get_value::<u8>() as usize) {
+      let pool = self.pool(*current);
+      let mut seed = block_hash.clone();
+      seed.push(*current as u8);
+
+      // The pool may have shrunk since the last batch
+      let mut index = cursor.get_value::<u128>().min(pool.count());
+      while index > 1 && remaining > 0 {
+        index -= 1;
+        pool.swap(index, seeded_index(&seed, index, index + 1))?;
+        remaining -= 1;
+      }
+      cursor.set_value::<u128>(index);
+
+      if index > 1 {
+        break;
+      }
+
+      let next = tier.get_value::<u8>() + 1;
+      tier.set_value::<u8>(next);
+      if let Some(next_tier) = SHUFFLE_TIERS.get(next as usize) {
+        cursor.set_value::<u128>(self.pool(*next_tier).count());
+      }
+    }
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = vec![(tier.get_value::<u8>() as usize >= SHUFFLE_TIERS.len()) as u8];
+
+    Ok(response)
+  }
+
+  fn shuffle_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/shuffle")
+  }
+}
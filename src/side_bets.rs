@@ -0,0 +1,147 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{
+  alkane_id_from_bytes, alkane_id_to_bytes,
+  pending::{txid_key, RESOLVE_DELAY_BLOCKS, STATE_COMMITTED},
+  PandaRoll, RollOutcome,
+};
+
+const SIDE_LOSE: u128 = 0;
+const SIDE_WIN: u128 = 1;
+
+// Neither side won, the roll pushed or was refunded, so every bet comes back
+const SIDE_NONE: u128 = 2;
+
+const STATE_SETTLED: u8 = 1;
+
+// Parimutuel bets by spectators on whether a committed roll will win, taken in
+// the payment token. Betting closes before the block whose hash the roll can be
+// resolved on, the book settles when the roll resolves or is refunded, and the
+// winning side then splits the whole pot in proportion to its stakes.
+impl PandaRoll {
+  pub(crate) fn place_side_bet(&self, txid_lo: u128, txid_hi: u128, side: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let key = txid_key(txid_lo, txid_hi);
+
+    if side != SIDE_LOSE && side != SIDE_WIN {
+      return Err(anyhow!("Invalid side bet side"));
+    }
+
+    let pending = self.pending_roll_pointer(&key);
+    if pending.keyword("/state").get_value::<u8>() != STATE_COMMITTED {
+      return Err(anyhow!("Roll is not awaiting resolution"));
+    }
+
+    if self.height() + 1 >= pending.keyword("/height").get_value::<u64>() + RESOLVE_DELAY_BLOCKS {
+      return Err(anyhow!("Side bets on this roll are closed"));
+    }
+
+    // Bets stay in the token the book opened with
+    let book = self.side_bet_pointer(&key);
+    let mut book_token = book.keyword("/token");
+    if book_token.get().len() == 0 {
+      book_token.set(Arc::new(alkane_id_to_bytes(&self.payment_token()?)));
+    }
+
+    let token = alkane_id_from_bytes(&book_token.get())?;
+    let amount = self.incoming_amount(&context, &token);
+    if amount == 0 {
+      return Err(anyhow!("Must send the side bet token"));
+    }
+
+    let remaining = self.take_incoming(&context, &token, amount)?;
+
+    let mut bet = self.side_bet_side_pointer(&key, side).select(&alkane_id_to_bytes(&context.caller));
+    let staked = bet.get_value::<u128>().checked_add(amount)
+      .ok_or_else(|| anyhow!("side bet overflow"))?;
+    bet.set_value::<u128>(staked);
+
+    let mut total = self.side_bet_side_pointer(&key, side);
+    let side_total = total.get_value::<u128>().checked_add(amount)
+      .ok_or_else(|| anyhow!("side bet total overflow"))?;
+    total.set_value::<u128>(side_total);
+
+    Ok(CallResponse::forward(&remaining))
+  }
+
+  // Called wherever a committed roll leaves the registry; `None` for a refund
+  pub(crate) fn settle_side_bets(&self, key: &Vec<u8>, outcome: Option<&RollOutcome>) {
+    let book = self.side_bet_pointer(key);
+    if book.keyword("/token").get().len() == 0 {
+      return;
+    }
+
+    let side = match outcome {
+      Some(RollOutcome::Win(_)) => SIDE_WIN,
+      Some(RollOutcome::Lose) => SIDE_LOSE,
+      _ => SIDE_NONE,
+    };
+
+    book.keyword("/outcome").set_value::<u128>(side);
+    book.keyword("/state").set_value::<u8>(STATE_SETTLED);
+  }
+
+  pub(crate) fn claim_side_bet(&self, txid_lo: u128, txid_hi: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let key = txid_key(txid_lo, txid_hi);
+
+    let book = self.side_bet_pointer(&key);
+    if book.keyword("/state").get_value::<u8>() != STATE_SETTLED {
+      return Err(anyhow!("Side bets on this roll are not settled"));
+    }
+
+    let outcome = book.keyword("/outcome").get_value::<u128>();
+    let (winning_total, losing_total) = match outcome {
+      SIDE_NONE => (0, 0),
+      _ => (
+        self.side_bet_side_pointer(&key, outcome).get_value::<u128>(),
+        self.side_bet_side_pointer(&key, 1 - outcome).get_value::<u128>(),
+      ),
+    };
+
+    // With nobody on the winning side every bet is refunded
+    let sides = if winning_total == 0 { vec![SIDE_LOSE, SIDE_WIN] } else { vec![outcome] };
+
+    let mut payout = 0u128;
+    for side in sides {
+      let mut bet = self.side_bet_side_pointer(&key, side).select(&alkane_id_to_bytes(&context.caller));
+      let staked = bet.get_value::<u128>();
+      bet.set_value::<u128>(0);
+
+      payout = payout.checked_add(if winning_total == 0 {
+        staked
+      } else {
+        staked.checked_mul(losing_total)
+          .map(|share| staked + share / winning_total)
+          .ok_or_else(|| anyhow!("side bet payout overflow"))?
+      }).ok_or_else(|| anyhow!("side bet payout overflow"))?;
+    }
+
+    if payout == 0 {
+      return Err(anyhow!("Nothing to claim for this roll"));
+    }
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.alkanes.0.push(AlkaneTransfer {
+      id: alkane_id_from_bytes(&book.keyword("/token").get())?,
+      value: payout,
+    });
+
+    Ok(response)
+  }
+
+  fn side_bet_pointer(&self, key: &Vec<u8>) -> StoragePointer {
+    StoragePointer::from_keyword("/side-bets/").select(key)
+  }
+
+  // The side's total stake lives at the base key, each bettor's stake under it
+  fn side_bet_side_pointer(&self, key: &Vec<u8>, side: u128) -> StoragePointer {
+    self.side_bet_pointer(key).keyword("/sides/").select(&side.to_le_bytes().to_vec())
+  }
+}
@@ -0,0 +1,319 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::{sha256, Hash};
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, schema::SCHEMA_VERSION, stack::PandaStack, PandaRoll, PrizeTier, PANDA_BLOCK};
+
+// Stack entries per ExportState page after the header page
+const SNAPSHOT_PAGE_SIZE: u128 = 250;
+
+// Tags a valid id entry, which carries the panda's rarity and traits
+const VALID_ID_TAG: u8 = 3;
+
+// Every page starts with the schema version, its own page number and the total
+// page count, all u128. Page 0 then carries the config and counts:
+//
+//   config word count u128 and the GetConfig words, each u128,
+//   the set win band and parlay thresholds u8 each (0 while unset),
+//   common, rare and jackpot counts u128, valid id count u128, rolls logged u128,
+//   guarded roll count u128 and the 32 byte digest of the guard set,
+//   promo count u128 and each promo's start and end heights u128 and threshold u8
+//
+// and every later page up to SNAPSHOT_PAGE_SIZE entries, each a tag u8 and the
+// 32 byte panda id. The valid ids come first (tag 3, followed by rarity and
+// traits u8), then the common, rare and jackpot stacks (tags 0, 1 and 2), each
+// in stack order.
+impl PandaRoll {
+  pub(crate) fn export_state(&self, page: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let sections = self.snapshot_sections();
+    let entries = sections.iter().map(|(_, stack)| stack.count()).sum::<u128>();
+    let pages = 1 + entries.div_ceil(SNAPSHOT_PAGE_SIZE);
+    if page >= pages {
+      return Err(anyhow!("Snapshot page out of range"));
+    }
+
+    let mut data = self.schema_version().to_le_bytes().to_vec();
+    data.extend_from_slice(&page.to_le_bytes());
+    data.extend_from_slice(&pages.to_le_bytes());
+
+    if page == 0 {
+      let config = self.config_words()?;
+      data.extend_from_slice(&(config.len() as u128).to_le_bytes());
+      for word in config {
+        data.extend_from_slice(&word.to_le_bytes());
+      }
+      data.push(self.threshold_pointer().get_value::<u8>());
+      data.push(self.parlay_threshold_pointer().get_value::<u8>());
+
+      for stack in self.snapshot_stacks().iter() {
+        data.extend_from_slice(&stack.count().to_le_bytes());
+      }
+      data.extend_from_slice(&self.valid_ids().count().to_le_bytes());
+      data.extend_from_slice(&self.rolls_count().to_le_bytes());
+
+      let (guarded, digest) = self.tx_hash_summary();
+      data.extend_from_slice(&guarded.to_le_bytes());
+      data.extend_from_slice(&digest);
+
+      let promos = self.promos();
+      data.extend_from_slice(&(promos.len() as u128).to_le_bytes());
+      for (start_height, end_height, threshold) in promos {
+        data.extend_from_slice(&start_height.to_le_bytes());
+        data.extend_from_slice(&end_height.to_le_bytes());
+        data.push(threshold);
+      }
+    } else {
+      // Skip whole stacks until the page's first entry, then read on across them
+      let mut skip = (page - 1) * SNAPSHOT_PAGE_SIZE;
+      let mut remaining = SNAPSHOT_PAGE_SIZE;
+
+      for (tag, stack) in sections.iter() {
+        let count = stack.count();
+        if skip >= count {
+          skip -= count;
+          continue;
+        }
+
+        let end = skip.saturating_add(remaining).min(count);
+        for index in skip..end {
+          let id = stack.get(index)?;
+          data.push(*tag);
+          data.extend(alkane_id_to_bytes(&id));
+
+          if *tag == VALID_ID_TAG {
+            data.push(self.rarity(id.tx));
+            data.push(self.traits(id.tx));
+          }
+        }
+
+        remaining -= end - skip;
+        skip = 0;
+
+        if remaining == 0 {
+          break;
+        }
+      }
+    }
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  // Replays ExportState pages into a fresh deployment, in page order starting at
+  // 0. The page's bytes follow `length` in the call inputs, packed into
+  // little-endian u128 words, and every panda a page lists has to come in with
+  // the call. Page 0 empties the valid id set seeded at Initialize, and the
+  // snapshot's own valid ids come before the stacks, so each panda's rarity is
+  // in place before it is weighed into a pool. Only the summary of the roll guard
+  // set carries over, not the guards themselves, and depositors, stats, player
+  // records and the roll log are not carried over either, so the new deployment
+  // starts them from zero.
+  pub(crate) fn import_state(&self, length: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    // inputs[0] is the opcode and inputs[1] the length
+    let bytes = context.inputs.iter().skip(2)
+      .flat_map(|word| word.to_le_bytes())
+      .collect::<Vec<u8>>();
+    let length = usize::try_from(length).ok()
+      .filter(|length| *length <= bytes.len())
+      .ok_or_else(|| anyhow!("Snapshot page is shorter than its length"))?;
+    let mut data = &bytes[..length];
+
+    if read_u128(&mut data)? != SCHEMA_VERSION {
+      return Err(anyhow!("Snapshot is from a different schema version"));
+    }
+
+    let import = self.import_pointer();
+    let mut next_page = import.keyword("/next-page");
+    let page = read_u128(&mut data)?;
+    if page != next_page.get_value::<u128>() {
+      return Err(anyhow!("Snapshot pages must be imported in order"));
+    }
+
+    let pages = read_u128(&mut data)?;
+    let mut total_pages = import.keyword("/pages");
+    let stacks = self.snapshot_stacks();
+    let mut remaining = context.incoming_alkanes.clone();
+
+    if page == 0 {
+      if stacks.iter().any(|stack| stack.count() > 0) {
+        return Err(anyhow!("State can only be imported into an empty deployment"));
+      }
+
+      let config_length = read_u128(&mut data)?;
+      let config = (0..config_length).map(|_| read_u128(&mut data)).collect::<Result<Vec<u128>>>()?;
+      self.restore_config_words(&config)?;
+      self.threshold_pointer().set_value::<u8>(read(&mut data, 1)?[0]);
+      self.parlay_threshold_pointer().set_value::<u8>(read(&mut data, 1)?[0]);
+
+      for tag in 0..stacks.len() {
+        import.keyword("/counts/").select(&vec![tag as u8]).set_value::<u128>(read_u128(&mut data)?);
+      }
+
+      // The snapshot's valid ids replace the seeded ones
+      self.clear_valid_ids()?;
+      import.keyword("/counts/").select(&vec![VALID_ID_TAG]).set_value::<u128>(read_u128(&mut data)?);
+
+      // The roll log length is only there to read
+      read_u128(&mut data)?;
+
+      let guarded = read_u128(&mut data)?;
+      let digest: [u8; 32] = read(&mut data, 32)?.try_into().unwrap();
+      self.set_tx_hash_summary(guarded, &digest);
+
+      for _ in 0..read_u128(&mut data)? {
+        let start_height = read_u128(&mut data)?;
+        let end_height = read_u128(&mut data)?;
+        self.add_promo(start_height, end_height, read(&mut data, 1)?[0]);
+      }
+
+      total_pages.set_value::<u128>(pages);
+    } else {
+      if page >= total_pages.get_value::<u128>() {
+        return Err(anyhow!("Snapshot page out of range"));
+      }
+
+      let mut batches = [Vec::new(), Vec::new(), Vec::new()];
+      while !data.is_empty() {
+        let tag = read(&mut data, 1)?[0];
+        let id = alkane_id_from_bytes(read(&mut data, 32)?)?;
+
+        // Valid ids go in as they are read, ahead of any panda that needs them
+        if tag == VALID_ID_TAG {
+          if id.block != PANDA_BLOCK {
+            return Err(anyhow!("Invalid Panda ID"));
+          }
+
+          let attributes = read(&mut data, 2)?;
+          self.add_valid_ids(&[id.tx])?;
+          self.restore_attributes(id.tx, attributes[0], attributes[1])?;
+          continue;
+        }
+
+        let tag = tag as usize;
+        if tag >= batches.len() {
+          return Err(anyhow!("Invalid snapshot stack tag"));
+        }
+
+        if !self.accepts_panda(&id)? {
+          return Err(anyhow!("Invalid Panda ID"));
+        }
+
+        if batches.iter().any(|batch| batch.contains(&id)) || self.snapshot_holds(&stacks, &id)? {
+          return Err(anyhow!("Panda is already in the imported state"));
+        }
+
+        // Each listed panda is taken out of what came in with the call
+        let transfer = remaining.0.iter_mut()
+          .find(|alkane| alkane.id == id && alkane.value > 0)
+          .ok_or_else(|| anyhow!("Snapshot pandas must be sent with their page"))?;
+        transfer.value -= 1;
+
+        batches[tag].push(id);
+      }
+      remaining.0.retain(|alkane| alkane.value > 0);
+
+      self.observe_markets();
+      for (stack, batch) in stacks.iter().zip(batches.iter()) {
+        stack.extend(batch)?;
+      }
+    }
+
+    // Once the last page is in, every stack has to match the counts on page 0
+    if page + 1 == total_pages.get_value::<u128>() {
+      for (tag, stack) in stacks.iter().enumerate() {
+        if stack.count() != import.keyword("/counts/").select(&vec![tag as u8]).get_value::<u128>() {
+          return Err(anyhow!("Imported stacks do not match the snapshot counts"));
+        }
+      }
+
+      if self.valid_ids().count() != import.keyword("/counts/").select(&vec![VALID_ID_TAG]).get_value::<u128>() {
+        return Err(anyhow!("Imported valid ids do not match the snapshot count"));
+      }
+    }
+
+    next_page.set_value::<u128>(page + 1);
+    self.emit_config_change(&context);
+
+    let mut response = CallResponse::forward(&remaining);
+    response.data = (page + 1).to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // SHA-256 over the schema version, GetConfig and GetStats words, the roll log
+  // length, and the count and member digest of each prize stack and the valid id
+  // set. Every part is kept up to date as play goes on, so the root costs the same
+  // however much the contract holds.
+  pub(crate) fn get_state_root(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let mut preimage = self.schema_version().to_le_bytes().to_vec();
+    for word in self.config_words()?.into_iter().chain(self.stats_words()).chain([self.rolls_count()]) {
+      preimage.extend_from_slice(&word.to_le_bytes());
+    }
+
+    let [common, rare, jackpot] = self.snapshot_stacks();
+    for stack in [common, rare, jackpot, self.valid_ids()] {
+      preimage.extend_from_slice(&stack.count().to_le_bytes());
+      preimage.extend_from_slice(&stack.digest());
+    }
+
+    response.data = sha256::Hash::hash(&preimage).to_byte_array().to_vec();
+
+    Ok(response)
+  }
+
+  fn snapshot_holds(&self, stacks: &[PandaStack], id: &AlkaneId) -> Result<bool> {
+    for stack in stacks {
+      if stack.find(id)?.is_some() {
+        return Ok(true);
+      }
+    }
+
+    Ok(false)
+  }
+
+  fn import_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/import")
+  }
+
+  // In the order of their stack tags
+  fn snapshot_stacks(&self) -> [PandaStack; 3] {
+    [self.pool(PrizeTier::Common), self.pool(PrizeTier::Rare), self.jackpot()]
+  }
+
+  // Every tagged section in the order ExportState pages through them
+  fn snapshot_sections(&self) -> Vec<(u8, PandaStack)> {
+    let mut sections = vec![(VALID_ID_TAG, self.valid_ids())];
+    sections.extend(self.snapshot_stacks().into_iter().enumerate().map(|(tag, stack)| (tag as u8, stack)));
+
+    sections
+  }
+}
+
+fn read<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+  if data.len() < length {
+    return Err(anyhow!("Snapshot page is truncated"));
+  }
+
+  let (head, rest) = data.split_at(length);
+  *data = rest;
+
+  Ok(head)
+}
+
+fn read_u128(data: &mut &[u8]) -> Result<u128> {
+  Ok(u128::from_le_bytes(read(data, 16)?.try_into().unwrap()))
+}
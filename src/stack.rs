@@ -0,0 +1,736 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::storage::StoragePointer;
+use alkanes_support::id::AlkaneId;
+
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::{sha256, Hash};
+use std::sync::{Arc, OnceLock};
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes};
+
+// A contiguous run of pandas held by the contract, used as a deque. The count
+// lives at the base key itself and the head slot at `key ++ "/head"`, so entry
+// `i` sits in slot `head + i` at `key ++ (head + i + 1)` and the tail is
+// `head + count`. Popping from the front just moves the head, leaving every
+// other entry where it is. Each panda's own `slot + 1` is indexed under
+// `key ++ "/positions/" ++ id` so lookups skip the scan, and `key ++ "/digest"`
+// holds the XOR of every member's SHA-256, which commits to the set of pandas
+// held whatever their order.
+//
+// A stack built `with_merkle_tree` also keeps a binary Merkle tree over its
+// slots under `key ++ "/merkle/" ++ level ++ index`, updated along with every
+// write, so a single panda's membership can be proven against the root alone.
+// The tree is only as deep as the highest slot used needs, its depth stored at
+// `key ++ "/merkle-depth"`, and gains a level whenever the stack outgrows it.
+//
+// A stack built `with_weights` gives every entry a weight, stored per slot under
+// `key ++ "/weights/"`, and keeps a Fenwick tree of them under
+// `key ++ "/weight-tree/"` so a weighted draw costs a walk down the tree rather
+// than a scan. An entry's weight moves with it when the stack reorders.
+pub struct PandaStack {
+  key: Vec<u8>,
+  merkle: bool,
+  weigh: Option<fn(&AlkaneId) -> u128>,
+}
+
+// Deepest a stack's Merkle tree can grow, one level for each bit of a slot
+const MAX_MERKLE_DEPTH: u8 = 127;
+
+impl PandaStack {
+  pub fn new(keyword: &str) -> Self {
+    Self::at(&StoragePointer::from_keyword(keyword))
+  }
+
+  pub fn at(pointer: &StoragePointer) -> Self {
+    Self { key: pointer.unwrap().to_vec(), merkle: false, weigh: None }
+  }
+
+  pub fn with_merkle_tree(mut self) -> Self {
+    self.merkle = true;
+    self
+  }
+
+  // `weigh` gives each entry its weight as it joins the stack
+  pub fn with_weights(mut self, weigh: fn(&AlkaneId) -> u128) -> Self {
+    self.weigh = Some(weigh);
+    self
+  }
+
+  fn pointer(&self) -> StoragePointer {
+    StoragePointer::wrap(&self.key)
+  }
+
+  fn head_pointer(&self) -> StoragePointer {
+    self.pointer().keyword("/head")
+  }
+
+  fn head(&self) -> u128 {
+    self.head_pointer().get_value::<u128>()
+  }
+
+  fn slot(&self, index: u128) -> Result<u128> {
+    self.head().checked_add(index)
+      .ok_or_else(|| anyhow!("instances slot overflow"))
+  }
+
+  fn slot_pointer(&self, slot: u128) -> StoragePointer {
+    self.pointer().select(&(slot + 1).to_le_bytes().to_vec())
+  }
+
+  fn position_pointer(&self, instance_id: &AlkaneId) -> StoragePointer {
+    self.pointer().keyword("/positions/").select(&alkane_id_to_bytes(instance_id))
+  }
+
+  // Points `instance_id` at `position`, 0 for none, and toggles it in or out of
+  // the digest when that changes whether it is a member
+  fn index_position(&self, instance_id: &AlkaneId, position: u128) {
+    let mut pointer = self.position_pointer(instance_id);
+    let was_member = pointer.get_value::<u128>() != 0;
+    pointer.set_value::<u128>(position);
+
+    if was_member != (position != 0) {
+      self.toggle_digest(&[*instance_id]);
+    }
+  }
+
+  fn toggle_digest(&self, instance_ids: &[AlkaneId]) {
+    let mut digest = self.digest();
+    for instance_id in instance_ids {
+      let hash = sha256::Hash::hash(&alkane_id_to_bytes(instance_id)).to_byte_array();
+      for (byte, hash_byte) in digest.iter_mut().zip(hash.iter()) {
+        *byte ^= hash_byte;
+      }
+    }
+
+    self.pointer().keyword("/digest").set(Arc::new(digest.to_vec()));
+  }
+
+  pub fn digest(&self) -> [u8; 32] {
+    self.pointer().keyword("/digest").get().as_slice().try_into().unwrap_or([0u8; 32])
+  }
+
+  pub fn count(&self) -> u128 {
+    self.pointer().get_value::<u128>()
+  }
+
+  fn set_count(&self, count: u128) {
+    self.pointer().set_value::<u128>(count);
+  }
+
+  pub fn push(&self, instance_id: &AlkaneId) -> Result<u128> {
+    let count = self.count();
+    let new_count = count.checked_add(1)
+      .ok_or_else(|| anyhow!("instances count overflow"))?;
+
+    self.set(count, instance_id)?;
+    self.set_count(new_count);
+
+    Ok(new_count)
+  }
+
+  // Appends every instance in order. The head, count and base pointers are read
+  // once for the whole batch and the count is written once at the end.
+  pub fn extend(&self, instance_ids: &[AlkaneId]) -> Result<u128> {
+    let count = self.count();
+    let new_count = count.checked_add(instance_ids.len() as u128)
+      .ok_or_else(|| anyhow!("instances count overflow"))?;
+
+    let tail = self.slot(count)?;
+    tail.checked_add(instance_ids.len() as u128)
+      .ok_or_else(|| anyhow!("instances slot overflow"))?;
+
+    let entries = self.pointer();
+    let positions = entries.keyword("/positions/");
+    let mut joined = Vec::new();
+    let mut leaves = Vec::new();
+    for (offset, instance_id) in instance_ids.iter().enumerate() {
+      let slot = tail + offset as u128;
+      entries.select(&(slot + 1).to_le_bytes().to_vec()).set(Arc::new(alkane_id_to_bytes(instance_id)));
+
+      let mut position = positions.select(&alkane_id_to_bytes(instance_id));
+      if position.get_value::<u128>() == 0 {
+        joined.push(*instance_id);
+      }
+      position.set_value::<u128>(slot + 1);
+      leaves.push((slot, merkle_leaf(instance_id)));
+      self.set_slot_weight(slot, self.weight_of(instance_id))?;
+    }
+    self.toggle_digest(&joined);
+    self.update_merkle_tree(&leaves)?;
+    self.set_count(new_count);
+
+    Ok(new_count)
+  }
+
+  pub fn set(&self, index: u128, instance_id: &AlkaneId) -> Result<()> {
+    self.write(self.slot(index)?, instance_id, self.weight_of(instance_id))
+  }
+
+  fn write(&self, slot: u128, instance_id: &AlkaneId, weight: u128) -> Result<()> {
+    self.slot_pointer(slot).set(Arc::new(alkane_id_to_bytes(instance_id)));
+    self.index_position(instance_id, slot + 1);
+    self.update_merkle_tree(&[(slot, merkle_leaf(instance_id))])?;
+    self.set_slot_weight(slot, weight)
+  }
+
+  // Takes the newest instance off the tail
+  pub fn pop(&self) -> Result<AlkaneId> {
+    let count = self.count();
+
+    let new_count = count.checked_sub(1)
+      .ok_or_else(|| anyhow!("instances count underflow"))?;
+
+    let instance_id = self.clear(self.slot(new_count)?)?;
+    self.set_count(new_count);
+
+    Ok(instance_id)
+  }
+
+  // Takes the oldest instance off the head
+  pub fn pop_front(&self) -> Result<AlkaneId> {
+    let count = self.count();
+
+    let new_count = count.checked_sub(1)
+      .ok_or_else(|| anyhow!("instances count underflow"))?;
+
+    let head = self.head();
+    let instance_id = self.clear(head)?;
+
+    self.head_pointer().set_value::<u128>(head + 1);
+    self.set_count(new_count);
+
+    Ok(instance_id)
+  }
+
+  // Empties a slot and drops its instance from the position index
+  fn clear(&self, slot: u128) -> Result<AlkaneId> {
+    let instance_id = self.decode(&self.slot_pointer(slot).get())?;
+
+    self.slot_pointer(slot).set(Arc::new(Vec::new()));
+    self.index_position(&instance_id, 0);
+    self.update_merkle_tree(&[(slot, [0u8; 32])])?;
+    self.set_slot_weight(slot, 0)?;
+
+    Ok(instance_id)
+  }
+
+  // Moves the last instance into the vacated slot so the stack stays contiguous
+  pub fn remove(&self, index: u128) -> Result<AlkaneId> {
+    let count = self.count();
+    if index >= count {
+      return Err(anyhow!("Instance index out of range"));
+    }
+
+    let instance_id = self.get(index)?;
+    let last_weight = self.slot_weight(self.slot(count - 1)?);
+    let last_id = self.pop()?;
+
+    if index != count - 1 {
+      self.index_position(&instance_id, 0);
+      self.write(self.slot(index)?, &last_id, last_weight)?;
+    }
+
+    Ok(instance_id)
+  }
+
+  pub fn swap(&self, a: u128, b: u128) -> Result<()> {
+    let count = self.count();
+    if a >= count || b >= count {
+      return Err(anyhow!("Instance index out of range"));
+    }
+
+    if a != b {
+      let (slot_a, slot_b) = (self.slot(a)?, self.slot(b)?);
+      let (id_a, weight_a) = (self.get(a)?, self.slot_weight(slot_a));
+      let (id_b, weight_b) = (self.get(b)?, self.slot_weight(slot_b));
+      self.write(slot_a, &id_b, weight_b)?;
+      self.write(slot_b, &id_a, weight_a)?;
+    }
+
+    Ok(())
+  }
+
+  // Rewrites the position index for up to `limit` entries from `start`, for
+  // stacks written before the index existed. Returns the next index to do.
+  pub fn reindex(&self, start: u128, limit: u128) -> Result<u128> {
+    let end = start.saturating_add(limit).min(self.count());
+
+    for index in start.min(end)..end {
+      let slot = self.slot(index)?;
+      let instance_id = self.decode(&self.slot_pointer(slot).get())?;
+      self.index_position(&instance_id, slot + 1);
+    }
+
+    Ok(end)
+  }
+
+  // Rewrites the Merkle leaves for up to `limit` entries from `start`, for stacks
+  // filled before they kept a tree. Returns the next index to do.
+  pub fn rehash(&self, start: u128, limit: u128) -> Result<u128> {
+    let end = start.saturating_add(limit).min(self.count());
+
+    let mut leaves = Vec::new();
+    for index in start.min(end)..end {
+      leaves.push((self.slot(index)?, merkle_leaf(&self.get(index)?)));
+    }
+    self.update_merkle_tree(&leaves)?;
+
+    Ok(end)
+  }
+
+  // Gives entry `index` its weight from `weigh` again, after whatever it was
+  // weighed on has changed or while it was held at another weight
+  pub fn reweigh(&self, index: u128) -> Result<()> {
+    self.set_weight(index, self.weight_of(&self.get(index)?))
+  }
+
+  // Reweighs up to `limit` entries from `start`, for stacks filled before they
+  // kept weights. Returns the next index to do.
+  pub fn reweigh_range(&self, start: u128, limit: u128) -> Result<u128> {
+    let end = start.saturating_add(limit).min(self.count());
+
+    for index in start.min(end)..end {
+      self.reweigh(index)?;
+    }
+
+    Ok(end)
+  }
+
+  pub fn set_weight(&self, index: u128, weight: u128) -> Result<()> {
+    if index >= self.count() {
+      return Err(anyhow!("Instance index out of range"));
+    }
+
+    self.set_slot_weight(self.slot(index)?, weight)
+  }
+
+  pub fn total_weight(&self) -> u128 {
+    match self.weight_tree_size() {
+      0 => 0,
+      size => self.weight_node(size),
+    }
+  }
+
+  // The entry whose share of the running total of weights covers `point`, which
+  // must be below `total_weight`
+  pub fn weighted_index(&self, point: u128) -> Result<u128> {
+    if point >= self.total_weight() {
+      return Err(anyhow!("Weighted point out of range"));
+    }
+
+    // Walks down from the root, keeping the last node whose running total stays
+    // at or below the point, so `slot` ends on the entry that crosses it
+    let size = self.weight_tree_size();
+    let mut slot = 0u128;
+    let mut remaining = point;
+    let mut step = size;
+    while step > 0 {
+      let node = slot + step;
+      if node <= size {
+        let weight = self.weight_node(node);
+        if weight <= remaining {
+          slot = node;
+          remaining -= weight;
+        }
+      }
+      step >>= 1;
+    }
+
+    slot.checked_sub(self.head())
+      .filter(|index| *index < self.count())
+      .ok_or_else(|| anyhow!("Weighted point landed outside the stack"))
+  }
+
+  fn weight_of(&self, instance_id: &AlkaneId) -> u128 {
+    self.weigh.map_or(0, |weigh| weigh(instance_id))
+  }
+
+  fn slot_weight_pointer(&self, slot: u128) -> StoragePointer {
+    self.pointer().keyword("/weights/").select(&slot.to_le_bytes().to_vec())
+  }
+
+  fn slot_weight(&self, slot: u128) -> u128 {
+    self.slot_weight_pointer(slot).get_value::<u128>()
+  }
+
+  fn weight_tree_size_pointer(&self) -> StoragePointer {
+    self.pointer().keyword("/weight-tree-size")
+  }
+
+  // Always a power of two, or 0 before any weight is set
+  fn weight_tree_size(&self) -> u128 {
+    self.weight_tree_size_pointer().get_value::<u128>()
+  }
+
+  fn weight_node_pointer(&self, node: u128) -> StoragePointer {
+    self.pointer().keyword("/weight-tree/").select(&node.to_le_bytes().to_vec())
+  }
+
+  fn weight_node(&self, node: u128) -> u128 {
+    self.weight_node_pointer(node).get_value::<u128>()
+  }
+
+  fn set_slot_weight(&self, slot: u128, weight: u128) -> Result<()> {
+    if self.weigh.is_none() {
+      return Ok(());
+    }
+
+    let mut pointer = self.slot_weight_pointer(slot);
+    let old = pointer.get_value::<u128>();
+    if old == weight {
+      return Ok(());
+    }
+    pointer.set_value::<u128>(weight);
+
+    // Slot `s` is node `s + 1`. Doubling the tree only needs the old root copied
+    // up, since every node past it covers empty slots so far.
+    let node = slot.checked_add(1).ok_or_else(|| anyhow!("instances slot overflow"))?;
+    let mut size = self.weight_tree_size().max(1);
+    while size < node {
+      self.weight_node_pointer(size << 1).set_value::<u128>(self.weight_node(size));
+      size <<= 1;
+    }
+    self.weight_tree_size_pointer().set_value::<u128>(size);
+
+    let mut node = node;
+    while node <= size {
+      let mut pointer = self.weight_node_pointer(node);
+      let total = pointer.get_value::<u128>();
+      pointer.set_value::<u128>(if weight > old { total + (weight - old) } else { total - (old - weight) });
+      node += node & node.wrapping_neg();
+    }
+
+    Ok(())
+  }
+
+  fn merkle_node_pointer(&self, level: u8, index: u128) -> StoragePointer {
+    let mut node = vec![level];
+    node.extend_from_slice(&index.to_le_bytes());
+    self.pointer().keyword("/merkle/").select(&node)
+  }
+
+  fn merkle_node(&self, level: u8, index: u128, empty: &[[u8; 32]]) -> [u8; 32] {
+    self.merkle_node_pointer(level, index).get().as_slice().try_into().unwrap_or(empty[level as usize])
+  }
+
+  fn merkle_depth_pointer(&self) -> StoragePointer {
+    self.pointer().keyword("/merkle-depth")
+  }
+
+  // Deepens the tree to `depth` levels if it is shallower. The old root becomes
+  // the leftmost node of each new level, beside what is right of it.
+  fn grow_merkle_tree(&self, depth: u8) -> Result<u8> {
+    let mut pointer = self.merkle_depth_pointer();
+    let current = pointer.get_value::<u8>();
+    if depth <= current {
+      return Ok(current);
+    }
+
+    let empty = empty_subtrees();
+    for level in current..depth {
+      let left = self.merkle_node(level, 0, empty);
+      let right = self.merkle_node(level, 1, empty);
+      self.merkle_node_pointer(level + 1, 0).set(Arc::new(merkle_parent(&left, &right).to_vec()));
+    }
+    pointer.set_value::<u8>(depth);
+
+    Ok(depth)
+  }
+
+  // Fits the tree's depth to the slots in use, for trees built when every stack
+  // had the same fixed depth. Their lower levels already hold the right nodes.
+  pub fn fit_merkle_tree(&self) -> Result<()> {
+    let tail = self.head() + self.count();
+    if !self.merkle || tail == 0 {
+      return Ok(());
+    }
+
+    self.grow_merkle_tree(merkle_depth(tail - 1)?)?;
+
+    Ok(())
+  }
+
+  // Writes the given leaves, then rehashes each parent above them once per level
+  fn update_merkle_tree(&self, leaves: &[(u128, [u8; 32])]) -> Result<()> {
+    if !self.merkle || leaves.is_empty() {
+      return Ok(());
+    }
+
+    let empty = empty_subtrees();
+    let highest = leaves.iter().map(|(slot, _)| *slot).max().unwrap_or(0);
+    let depth = self.grow_merkle_tree(merkle_depth(highest)?)?;
+
+    let mut dirty = Vec::new();
+    for (slot, leaf) in leaves {
+      self.merkle_node_pointer(0, *slot).set(Arc::new(leaf.to_vec()));
+      dirty.push(*slot);
+    }
+
+    for level in 0..depth {
+      dirty = dirty.iter().map(|index| index >> 1).collect();
+      dirty.sort_unstable();
+      dirty.dedup();
+
+      for parent in dirty.iter() {
+        let left = self.merkle_node(level, parent << 1, empty);
+        let right = self.merkle_node(level, (parent << 1) | 1, empty);
+        self.merkle_node_pointer(level + 1, *parent).set(Arc::new(merkle_parent(&left, &right).to_vec()));
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn merkle_root(&self) -> Result<[u8; 32]> {
+    if !self.merkle {
+      return Err(anyhow!("Stack does not keep a Merkle tree"));
+    }
+
+    Ok(self.merkle_node(self.merkle_depth_pointer().get_value::<u8>(), 0, empty_subtrees()))
+  }
+
+  // The slot of entry `index` and its sibling hashes from the leaf up, one per
+  // level of the tree. Hashing `merkle_leaf` of the entry with each sibling in
+  // turn, on the left whenever that bit of the slot is set, gives back the root.
+  pub fn merkle_proof(&self, index: u128) -> Result<(u128, Vec<[u8; 32]>)> {
+    if !self.merkle {
+      return Err(anyhow!("Stack does not keep a Merkle tree"));
+    }
+
+    if index >= self.count() {
+      return Err(anyhow!("Instance index out of range"));
+    }
+
+    let slot = self.slot(index)?;
+    let empty = empty_subtrees();
+    let siblings = (0..self.merkle_depth_pointer().get_value::<u8>())
+      .map(|level| self.merkle_node(level, (slot >> level) ^ 1, empty))
+      .collect();
+
+    Ok((slot, siblings))
+  }
+
+  pub fn get(&self, index: u128) -> Result<AlkaneId> {
+    self.decode(&self.slot_pointer(self.slot(index)?).get())
+  }
+
+  fn decode(&self, bytes: &[u8]) -> Result<AlkaneId> {
+    if bytes.len() != 32 {
+      return Err(anyhow!("Invalid instance data length"));
+    }
+
+    alkane_id_from_bytes(bytes)
+  }
+
+  pub fn find(&self, id: &AlkaneId) -> Result<Option<u128>> {
+    let position = self.position_pointer(id).get_value::<u128>();
+    if position == 0 {
+      return Ok(None);
+    }
+
+    match (position - 1).checked_sub(self.head()) {
+      Some(index) if index < self.count() => Ok(Some(index)),
+      _ => Err(anyhow!("Stale instance position")),
+    }
+  }
+
+  // Oldest first
+  pub fn drain(&self) -> Result<Vec<AlkaneId>> {
+    let mut ids = Vec::new();
+
+    while self.count() > 0 {
+      ids.push(self.pop_front()?);
+    }
+
+    Ok(ids)
+  }
+}
+
+// Leaves and inner nodes hash under different prefixes, so neither can pass for
+// the other in a proof
+fn merkle_leaf(instance_id: &AlkaneId) -> [u8; 32] {
+  let mut preimage = vec![0u8];
+  preimage.extend_from_slice(&alkane_id_to_bytes(instance_id));
+  sha256::Hash::hash(&preimage).to_byte_array()
+}
+
+fn merkle_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+  let mut preimage = vec![1u8];
+  preimage.extend_from_slice(left);
+  preimage.extend_from_slice(right);
+  sha256::Hash::hash(&preimage).to_byte_array()
+}
+
+// Levels a tree needs before `slot` fits under its root
+fn merkle_depth(slot: u128) -> Result<u8> {
+  let depth = (u128::BITS - slot.leading_zeros()) as u8;
+  if depth > MAX_MERKLE_DEPTH {
+    return Err(anyhow!("Stack slot is beyond its Merkle tree"));
+  }
+
+  Ok(depth)
+}
+
+// The root of an all-empty subtree at each level, with empty slots as zero
+// leaves. Worked out once and shared by every stack.
+fn empty_subtrees() -> &'static [[u8; 32]] {
+  static EMPTY: OnceLock<Vec<[u8; 32]>> = OnceLock::new();
+
+  EMPTY.get_or_init(|| {
+    let mut empty = vec![[0u8; 32]];
+    for level in 0..MAX_MERKLE_DEPTH as usize {
+      empty.push(merkle_parent(&empty[level], &empty[level]));
+    }
+    empty
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The node at `level` over `leaves`, the way the stored tree holds it
+  fn node(leaves: &[[u8; 32]], level: u8, index: u128) -> [u8; 32] {
+    if (index << level) >= leaves.len() as u128 {
+      return empty_subtrees()[level as usize];
+    }
+
+    if level == 0 {
+      return leaves[index as usize];
+    }
+
+    merkle_parent(&node(leaves, level - 1, index << 1), &node(leaves, level - 1, (index << 1) | 1))
+  }
+
+  // Folds a leaf with its siblings the way `merkle_proof` says to
+  fn fold(leaf: [u8; 32], slot: u128, siblings: &[[u8; 32]]) -> [u8; 32] {
+    siblings.iter().enumerate().fold(leaf, |hash, (level, sibling)| {
+      if (slot >> level) & 1 == 1 { merkle_parent(sibling, &hash) } else { merkle_parent(&hash, sibling) }
+    })
+  }
+
+  fn leaves(count: u128) -> Vec<[u8; 32]> {
+    (0..count).map(|tx| merkle_leaf(&AlkaneId { block: 2, tx })).collect()
+  }
+
+  // Checks the stored root against one rebuilt from `slots`, the leaf each slot
+  // should hold, then every entry's proof against the stored root
+  fn assert_proves_every_entry(stack: &PandaStack, slots: &[[u8; 32]]) {
+    let root = stack.merkle_root().unwrap();
+    let depth = stack.merkle_depth_pointer().get_value::<u8>();
+    assert_eq!(root, node(slots, depth, 0));
+
+    for index in 0..stack.count() {
+      let (slot, siblings) = stack.merkle_proof(index).unwrap();
+      assert_eq!(siblings.len(), depth as usize);
+      assert_eq!(fold(merkle_leaf(&stack.get(index).unwrap()), slot, &siblings), root, "entry {}", index);
+    }
+  }
+
+  #[test]
+  fn depth_fits_the_highest_slot() {
+    assert_eq!(merkle_depth(0).unwrap(), 0);
+    assert_eq!(merkle_depth(1).unwrap(), 1);
+    assert_eq!(merkle_depth(3).unwrap(), 2);
+    assert_eq!(merkle_depth(4).unwrap(), 3);
+    assert_eq!(merkle_depth((1 << MAX_MERKLE_DEPTH) - 1).unwrap(), MAX_MERKLE_DEPTH);
+    assert!(merkle_depth(1 << MAX_MERKLE_DEPTH).is_err());
+  }
+
+  #[test]
+  fn empty_subtrees_hash_up_from_zero_leaves() {
+    let empty = empty_subtrees();
+
+    assert_eq!(empty.len(), MAX_MERKLE_DEPTH as usize + 1);
+    assert_eq!(empty[0], [0u8; 32]);
+    for level in 1..empty.len() {
+      assert_eq!(empty[level], merkle_parent(&empty[level - 1], &empty[level - 1]));
+    }
+  }
+
+  #[test]
+  fn every_proof_folds_to_the_root() {
+    for count in [1u128, 2, 3, 5, 8, 13] {
+      let leaves = leaves(count);
+      let depth = merkle_depth(count - 1).unwrap();
+      let root = node(&leaves, depth, 0);
+
+      for slot in 0..count {
+        let siblings = (0..depth).map(|level| node(&leaves, level, (slot >> level) ^ 1)).collect::<Vec<_>>();
+        assert_eq!(fold(leaves[slot as usize], slot, &siblings), root, "slot {} of {}", slot, count);
+      }
+    }
+  }
+
+  #[test]
+  fn a_proof_does_not_fit_another_leaf() {
+    let leaves = leaves(6);
+    let depth = merkle_depth(5).unwrap();
+    let root = node(&leaves, depth, 0);
+
+    let siblings = (0..depth).map(|level| node(&leaves, level, (2 >> level) ^ 1)).collect::<Vec<_>>();
+    assert_ne!(fold(leaves[3], 2, &siblings), root);
+    assert_ne!(fold(leaves[2], 3, &siblings), root);
+  }
+
+  #[test]
+  fn growing_keeps_the_old_root_on_the_left_spine() {
+    let leaves = leaves(3);
+    let root = node(&leaves, 2, 0);
+
+    assert_eq!(node(&leaves, 3, 0), merkle_parent(&root, &empty_subtrees()[2]));
+  }
+
+  #[test]
+  fn a_stored_tree_proves_every_entry_as_it_grows() {
+    let stack = PandaStack::new("/test/merkle/grow").with_merkle_tree();
+    let slots = leaves(11);
+
+    for tx in 0..4 {
+      stack.push(&AlkaneId { block: 2, tx }).unwrap();
+      assert_proves_every_entry(&stack, &slots[..tx as usize + 1]);
+    }
+
+    // One batch deep enough to add a level
+    let batch = (4..11).map(|tx| AlkaneId { block: 2, tx }).collect::<Vec<_>>();
+    stack.extend(&batch).unwrap();
+    assert_eq!(stack.merkle_depth_pointer().get_value::<u8>(), merkle_depth(10).unwrap());
+    assert_proves_every_entry(&stack, &slots);
+  }
+
+  #[test]
+  fn a_stored_tree_follows_removes_and_swaps() {
+    let stack = PandaStack::new("/test/merkle/reorder").with_merkle_tree();
+    let mut slots = leaves(7);
+    for tx in 0..7 {
+      stack.push(&AlkaneId { block: 2, tx }).unwrap();
+    }
+
+    // The last entry fills the hole
+    stack.remove(2).unwrap();
+    slots[2] = slots[6];
+    slots[6] = [0u8; 32];
+    assert_proves_every_entry(&stack, &slots);
+
+    stack.swap(0, 3).unwrap();
+    slots.swap(0, 3);
+    assert_proves_every_entry(&stack, &slots);
+
+    // Entries now start past slot 0
+    stack.pop_front().unwrap();
+    slots[0] = [0u8; 32];
+    assert_proves_every_entry(&stack, &slots);
+
+    stack.remove(0).unwrap();
+    slots[1] = slots[5];
+    slots[5] = [0u8; 32];
+    assert_proves_every_entry(&stack, &slots);
+
+    let joined = AlkaneId { block: 2, tx: 100 };
+    stack.push(&joined).unwrap();
+    slots[5] = merkle_leaf(&joined);
+    assert_proves_every_entry(&stack, &slots);
+    assert_eq!(stack.merkle_proof(stack.count() - 1).unwrap().0, 5);
+  }
+}
@@ -0,0 +1,57 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::response::CallResponse;
+
+use anyhow::Result;
+
+use crate::{PandaRoll, RollOutcome};
+
+// House-wide counters, in the order GetStats returns them
+const STATS: [&str; 5] = ["/rolls", "/wins", "/losses", "/deposited", "/paid-out"];
+
+impl PandaRoll {
+  // Total rolls, wins, losses, pandas deposited and pandas paid out, as u128s.
+  // Pushes count as rolls but neither wins nor losses.
+  pub(crate) fn get_stats(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.stats_words().iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    Ok(response)
+  }
+
+  pub(crate) fn stats_words(&self) -> Vec<u128> {
+    let stats = self.stats_pointer();
+    STATS.iter().map(|stat| stats.keyword(stat).get_value::<u128>()).collect()
+  }
+
+  pub(crate) fn count_settled_roll(&self, outcome: &RollOutcome) {
+    self.add_to_stat("/rolls", 1);
+
+    match outcome {
+      RollOutcome::Win(_) => self.add_to_stat("/wins", 1),
+      RollOutcome::Lose => self.add_to_stat("/losses", 1),
+      RollOutcome::Push => {}
+    }
+  }
+
+  pub(crate) fn count_deposited(&self, pandas: u128) {
+    self.add_to_stat("/deposited", pandas);
+  }
+
+  pub(crate) fn count_paid_out(&self, pandas: u128) {
+    self.add_to_stat("/paid-out", pandas);
+  }
+
+  fn add_to_stat(&self, stat: &str, amount: u128) {
+    let mut pointer = self.stats_pointer().keyword(stat);
+    let total = pointer.get_value::<u128>();
+    pointer.set_value::<u128>(total.saturating_add(amount));
+  }
+
+  fn stats_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/stats")
+  }
+}
@@ -0,0 +1,186 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{parcel::AlkaneTransfer, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+
+use crate::{alkane_id_to_bytes, stack::PandaStack, PandaRoll};
+
+// Blocks a team battle stays open for joining after it is created
+const TEAM_BATTLE_JOIN_BLOCKS: u64 = 144;
+
+// Most pandas a single team can field
+const MAX_TEAM_SIZE: u128 = 32;
+
+const TEAM_COUNT: u128 = 2;
+
+const STATE_OPEN: u8 = 1;
+const STATE_RESOLVED: u8 = 2;
+
+// Stored as the winning team once resolved. A battle with an empty side is void
+// and every member just takes their own pandas back.
+const WINNER_VOID: u8 = 2;
+
+// Two teams each pool pandas, one panda per join. The winning team keeps its own
+// pandas and the losing team's pandas are dealt round-robin across the winning
+// entries, so each member's share follows how many pandas they put in.
+impl PandaRoll {
+  pub(crate) fn create_team_battle(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let battle_id = self.team_battles_count();
+    self.team_battles_pointer().set_value::<u128>(battle_id + 1);
+
+    let battle = self.team_battle_pointer(battle_id);
+    battle.keyword("/deadline").set_value::<u64>(self.height() + TEAM_BATTLE_JOIN_BLOCKS);
+    battle.keyword("/state").set_value::<u8>(STATE_OPEN);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = battle_id.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn join_team(&self, battle_id: u128, team: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let panda = self.single_panda(&context.incoming_alkanes.0)?;
+
+    if team >= TEAM_COUNT {
+      return Err(anyhow!("Invalid team"));
+    }
+
+    let battle = self.team_battle_pointer(battle_id);
+    if battle.keyword("/state").get_value::<u8>() != STATE_OPEN {
+      return Err(anyhow!("Team battle is not open"));
+    }
+
+    if self.height() >= battle.keyword("/deadline").get_value::<u64>() {
+      return Err(anyhow!("Team battle is closed for joining"));
+    }
+
+    let pandas = self.team_pandas(battle_id, team);
+    if pandas.count() >= MAX_TEAM_SIZE {
+      return Err(anyhow!("Team is full"));
+    }
+
+    pandas.push(&panda)?;
+    self.team_members(battle_id, team).push(&context.caller)?;
+
+    Ok(CallResponse::default())
+  }
+
+  // Anyone can resolve a battle once joining has closed
+  pub(crate) fn resolve_team_battle(&self, battle_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+
+    let battle = self.team_battle_pointer(battle_id);
+    if battle.keyword("/state").get_value::<u8>() != STATE_OPEN {
+      return Err(anyhow!("Team battle is not open"));
+    }
+
+    if self.height() < battle.keyword("/deadline").get_value::<u64>() {
+      return Err(anyhow!("Team battle is still open for joining"));
+    }
+
+    let winner = if (0..TEAM_COUNT).any(|team| self.team_pandas(battle_id, team).count() == 0) {
+      WINNER_VOID
+    } else if self.calculate_random_value(&txid)? < 128 {
+      0
+    } else {
+      1
+    };
+
+    battle.keyword("/winner").set_value::<u8>(winner);
+    battle.keyword("/state").set_value::<u8>(STATE_RESOLVED);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = vec![winner];
+
+    Ok(response)
+  }
+
+  pub(crate) fn claim_team_battle(&self, battle_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    let battle = self.team_battle_pointer(battle_id);
+    if battle.keyword("/state").get_value::<u8>() != STATE_RESOLVED {
+      return Err(anyhow!("Team battle is not resolved"));
+    }
+
+    let mut claimed = battle.keyword("/claimed/").select(&alkane_id_to_bytes(&context.caller));
+    if claimed.get_value::<u8>() != 0 {
+      return Err(anyhow!("Team battle already claimed"));
+    }
+
+    let winner = battle.keyword("/winner").get_value::<u8>();
+    let mut payout = Vec::new();
+
+    // Members of the winning team, or everyone in a void battle, take their own pandas back
+    for team in 0..TEAM_COUNT {
+      if winner != WINNER_VOID && team != winner as u128 {
+        continue;
+      }
+
+      let pandas = self.team_pandas(battle_id, team);
+      let members = self.team_members(battle_id, team);
+      let entries = members.count();
+
+      for entry in 0..entries {
+        if members.get(entry)? != context.caller {
+          continue;
+        }
+
+        payout.push(pandas.get(entry)?);
+
+        // Plus every losing panda dealt to this entry
+        if winner != WINNER_VOID {
+          let losing = self.team_pandas(battle_id, 1 - team);
+          let mut dealt = entry;
+          while dealt < losing.count() {
+            payout.push(losing.get(dealt)?);
+            dealt += entries;
+          }
+        }
+      }
+    }
+
+    if payout.len() == 0 {
+      return Err(anyhow!("Nothing to claim for this team battle"));
+    }
+
+    claimed.set_value::<u8>(1);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    for panda in payout {
+      response.alkanes.0.push(AlkaneTransfer {
+        id: panda,
+        value: 1u128,
+      });
+    }
+
+    Ok(response)
+  }
+
+  fn team_battles_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/team-battles/")
+  }
+
+  fn team_battles_count(&self) -> u128 {
+    self.team_battles_pointer().get_value::<u128>()
+  }
+
+  fn team_battle_pointer(&self, battle_id: u128) -> StoragePointer {
+    self.team_battles_pointer().select(&battle_id.to_le_bytes().to_vec())
+  }
+
+  fn team_pandas(&self, battle_id: u128, team: u128) -> PandaStack {
+    PandaStack::at(&self.team_battle_pointer(battle_id).keyword("/pandas/").select(&team.to_le_bytes().to_vec()))
+  }
+
+  // Entry `i` of a team's members staked entry `i` of its pandas
+  fn team_members(&self, battle_id: u128, team: u128) -> PandaStack {
+    PandaStack::at(&self.team_battle_pointer(battle_id).keyword("/members/").select(&team.to_le_bytes().to_vec()))
+  }
+}
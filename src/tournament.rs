@@ -0,0 +1,231 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{parcel::AlkaneTransfer, response::CallResponse};
+
+use bitcoin::hashes::{sha256, Hash};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::{alkane_id_from_bytes, alkane_id_to_bytes, stack::PandaStack, PandaRoll};
+
+const MAX_TOURNAMENT_SIZE: u128 = 64;
+
+// Blocks a tournament stays open for entries after it opens
+const ENTRY_WINDOW_BLOCKS: u64 = 144;
+
+const STATE_OPEN: u8 = 1;
+const STATE_RUNNING: u8 = 2;
+const STATE_FINISHED: u8 = 3;
+const STATE_CLAIMED: u8 = 4;
+const STATE_CANCELLED: u8 = 5;
+
+// Lifecycle: open for entries -> running one bracket round per block -> finished
+// with a single survivor -> claimed by that survivor. A tournament still short of
+// entrants when its entry window closes is cancelled instead, and every entrant
+// reclaims their own panda.
+impl PandaRoll {
+  pub(crate) fn open_tournament(&self, size: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    if size < 2 || size > MAX_TOURNAMENT_SIZE || !size.is_power_of_two() {
+      return Err(anyhow!("Tournament size must be a power of two up to 64"));
+    }
+
+    let mut count_pointer = StoragePointer::from_keyword("/tournaments/count");
+    let tournament_id = count_pointer.get_value::<u128>();
+    count_pointer.set_value::<u128>(tournament_id + 1);
+
+    let tournament = self.tournament_pointer(tournament_id);
+    tournament.keyword("/size").set_value::<u128>(size);
+    tournament.keyword("/deadline").set_value::<u64>(self.height() + ENTRY_WINDOW_BLOCKS);
+    tournament.keyword("/state").set_value::<u8>(STATE_OPEN);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = tournament_id.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn enter_tournament(&self, tournament_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let panda = self.single_panda(&context.incoming_alkanes.0)?;
+
+    let tournament = self.tournament_pointer(tournament_id);
+    if tournament.keyword("/state").get_value::<u8>() != STATE_OPEN
+      || self.height() >= tournament.keyword("/deadline").get_value::<u64>()
+    {
+      return Err(anyhow!("Tournament is not open for entries"));
+    }
+
+    let pool = self.tournament_pool(tournament_id);
+    let entrant = pool.count();
+    pool.push(&panda)?;
+
+    tournament.keyword("/entrants/")
+      .select(&entrant.to_le_bytes().to_vec())
+      .set(Arc::new(alkane_id_to_bytes(&context.caller)));
+    self.set_tournament_survivor(tournament_id, entrant, entrant);
+
+    let size = tournament.keyword("/size").get_value::<u128>();
+    if entrant + 1 == size {
+      tournament.keyword("/survivors").set_value::<u128>(size);
+      tournament.keyword("/state").set_value::<u8>(STATE_RUNNING);
+    }
+
+    let mut response = CallResponse::default();
+    response.data = entrant.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Plays one bracket round. Anyone can call this, but only once per block so
+  // each round draws on fresh block-hash entropy.
+  pub(crate) fn resolve_tournament_round(&self, tournament_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let tournament = self.tournament_pointer(tournament_id);
+
+    if tournament.keyword("/state").get_value::<u8>() != STATE_RUNNING {
+      return Err(anyhow!("Tournament is not running"));
+    }
+
+    let height = self.height();
+    if tournament.keyword("/last-height").get_value::<u64>() == height {
+      return Err(anyhow!("Tournament round already resolved this block"));
+    }
+    tournament.keyword("/last-height").set_value::<u64>(height);
+
+    let round = tournament.keyword("/round").get_value::<u128>();
+    let survivors = tournament.keyword("/survivors").get_value::<u128>();
+    let block_hash = self.block_hash()?;
+
+    for game in 0..survivors / 2 {
+      let mut preimage = block_hash.clone();
+      preimage.extend_from_slice(&tournament_id.to_le_bytes());
+      preimage.extend_from_slice(&round.to_le_bytes());
+      preimage.extend_from_slice(&game.to_le_bytes());
+
+      let slot = if sha256::Hash::hash(&preimage).to_byte_array()[0] < 128 { 2 * game } else { 2 * game + 1 };
+      let winner = self.tournament_survivor(tournament_id, slot);
+      self.set_tournament_survivor(tournament_id, game, winner);
+    }
+
+    tournament.keyword("/round").set_value::<u128>(round + 1);
+    tournament.keyword("/survivors").set_value::<u128>(survivors / 2);
+
+    if survivors / 2 == 1 {
+      tournament.keyword("/state").set_value::<u8>(STATE_FINISHED);
+    }
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn claim_tournament(&self, tournament_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let tournament = self.tournament_pointer(tournament_id);
+
+    if tournament.keyword("/state").get_value::<u8>() != STATE_FINISHED {
+      return Err(anyhow!("Tournament has no unclaimed winner"));
+    }
+
+    let winner = self.tournament_survivor(tournament_id, 0);
+    let entrant = tournament.keyword("/entrants/").select(&winner.to_le_bytes().to_vec()).get();
+    if alkane_id_from_bytes(&entrant)? != context.caller {
+      return Err(anyhow!("Caller did not win this tournament"));
+    }
+
+    tournament.keyword("/state").set_value::<u8>(STATE_CLAIMED);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    for panda in self.tournament_pool(tournament_id).drain()? {
+      response.alkanes.0.push(AlkaneTransfer {
+        id: panda,
+        value: 1u128,
+      });
+    }
+
+    Ok(response)
+  }
+
+  // Hands an entrant their panda back once the entry window has closed on a
+  // tournament that never filled
+  pub(crate) fn reclaim_tournament_entry(&self, tournament_id: u128, entrant: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let tournament = self.tournament_pointer(tournament_id);
+
+    match tournament.keyword("/state").get_value::<u8>() {
+      STATE_OPEN if self.height() >= tournament.keyword("/deadline").get_value::<u64>() => {
+        tournament.keyword("/state").set_value::<u8>(STATE_CANCELLED);
+      }
+      STATE_CANCELLED => {}
+      _ => return Err(anyhow!("Tournament entries cannot be reclaimed")),
+    }
+
+    let pool = self.tournament_pool(tournament_id);
+    if entrant >= pool.count() {
+      return Err(anyhow!("No such tournament entrant"));
+    }
+
+    let owner = tournament.keyword("/entrants/").select(&entrant.to_le_bytes().to_vec()).get();
+    if alkane_id_from_bytes(&owner)? != context.caller {
+      return Err(anyhow!("Caller did not make this entry"));
+    }
+
+    // Entries keep their index, so reclaimed ones are only marked
+    let mut reclaimed = tournament.keyword("/reclaimed/").select(&entrant.to_le_bytes().to_vec());
+    if reclaimed.get_value::<u8>() != 0 {
+      return Err(anyhow!("Entry already reclaimed"));
+    }
+    reclaimed.set_value::<u8>(1);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.alkanes.0.push(AlkaneTransfer {
+      id: pool.get(entrant)?,
+      value: 1u128,
+    });
+
+    Ok(response)
+  }
+
+  pub(crate) fn get_tournament(&self, tournament_id: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    let tournament = self.tournament_pointer(tournament_id);
+
+    let mut data = Vec::with_capacity(73);
+    data.push(tournament.keyword("/state").get_value::<u8>());
+    data.extend_from_slice(&tournament.keyword("/size").get_value::<u128>().to_le_bytes());
+    data.extend_from_slice(&self.tournament_pool(tournament_id).count().to_le_bytes());
+    data.extend_from_slice(&tournament.keyword("/round").get_value::<u128>().to_le_bytes());
+    data.extend_from_slice(&tournament.keyword("/survivors").get_value::<u128>().to_le_bytes());
+    data.extend_from_slice(&tournament.keyword("/deadline").get_value::<u64>().to_le_bytes());
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  fn tournament_pointer(&self, tournament_id: u128) -> StoragePointer {
+    StoragePointer::from_keyword("/tournaments/").select(&tournament_id.to_le_bytes().to_vec())
+  }
+
+  fn tournament_pool(&self, tournament_id: u128) -> PandaStack {
+    PandaStack::at(&self.tournament_pointer(tournament_id).keyword("/pool"))
+  }
+
+  // Survivor slots hold entrant indexes, compacted to the front after each round
+  fn tournament_survivor(&self, tournament_id: u128, slot: u128) -> u128 {
+    self.tournament_pointer(tournament_id)
+      .keyword("/bracket/")
+      .select(&slot.to_le_bytes().to_vec())
+      .get_value::<u128>()
+  }
+
+  fn set_tournament_survivor(&self, tournament_id: u128, slot: u128, entrant: u128) {
+    self.tournament_pointer(tournament_id)
+      .keyword("/bracket/")
+      .select(&slot.to_le_bytes().to_vec())
+      .set_value::<u128>(entrant);
+  }
+}
@@ -0,0 +1,68 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{
+  cellpack::Cellpack, id::AlkaneId, parcel::AlkaneTransferParcel, response::CallResponse,
+};
+
+use anyhow::Result;
+use std::sync::Arc;
+
+use crate::{alkane_id_to_bytes, PandaRoll};
+
+// Spawning from block 6 deploys a fresh instance of the template stored at [4, tx]
+const FACTORY_BLOCK: u128 = 6;
+
+impl PandaRoll {
+  // Trophies are deployed from this template. A template of 0 stops minting them.
+  pub(crate) fn set_trophy_template(&self, tx: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.trophy_template_pointer().set_value::<u128>(tx);
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // Deploys a one-of-one trophy for `winner`, initialised with its number and
+  // the winning multiplier, and records who won it and when
+  pub(crate) fn mint_trophy(&self, response: &mut CallResponse, winner: &AlkaneId, multiplier: u128) -> Result<()> {
+    let template = self.trophy_template_pointer().get_value::<u128>();
+    if template == 0 {
+      return Ok(());
+    }
+
+    let trophy_number = self.trophies_pointer().get_value::<u128>();
+    self.trophies_pointer().set_value::<u128>(trophy_number + 1);
+
+    let minted = self.spawn_from_template(template, vec![0, trophy_number, multiplier])?;
+
+    let trophy = self.trophies_pointer().select(&trophy_number.to_le_bytes().to_vec());
+    trophy.keyword("/winner").set(Arc::new(alkane_id_to_bytes(winner)));
+    trophy.keyword("/multiplier").set_value::<u128>(multiplier);
+    trophy.keyword("/height").set_value::<u64>(self.height());
+
+    response.alkanes.0.extend(minted.0);
+
+    Ok(())
+  }
+
+  // Deploys a new alkane from the template at [4, template] and returns what it minted
+  pub(crate) fn spawn_from_template(&self, template: u128, inputs: Vec<u128>) -> Result<AlkaneTransferParcel> {
+    let cellpack = Cellpack {
+      target: AlkaneId { block: FACTORY_BLOCK, tx: template },
+      inputs,
+    };
+
+    Ok(self.call(&cellpack, &AlkaneTransferParcel::default(), self.fuel())?.alkanes)
+  }
+
+  pub(crate) fn trophy_template_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/config/trophy-template")
+  }
+
+  fn trophies_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/trophies/")
+  }
+}
@@ -0,0 +1,153 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{auth::AuthenticatedResponder, runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{id::AlkaneId, response::CallResponse};
+
+use anyhow::{anyhow, Result};
+
+use crate::{panda_ids, stack::PandaStack, PandaRoll, PANDA_BLOCK};
+
+// Most ids a single load, add or remove call may touch, to stay within fuel
+const MAX_VALID_ID_BATCH: u128 = 500;
+
+// Ids per GetValidIds page
+const VALID_ID_PAGE: u128 = 500;
+
+// The set of panda ids the contract accepts lives in storage so the house can
+// correct it without a redeploy. Initialize loads the first page of the built-in
+// seed list, LoadValidIds pages in the rest, and AddValidIdRange /
+// RemoveValidIdRange adjust it by tx id range.
+impl PandaRoll {
+  // Loads the first page of the seed list into a fresh deployment
+  pub(crate) fn seed_valid_ids(&self) -> Result<()> {
+    self.load_seed_page(MAX_VALID_ID_BATCH)?;
+
+    Ok(())
+  }
+
+  // Loads the next `count` seed ids and returns how many have been loaded so far
+  pub(crate) fn load_valid_ids(&self, count: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    if count > MAX_VALID_ID_BATCH {
+      return Err(anyhow!("Too many ids in one batch"));
+    }
+
+    let loaded = self.load_seed_page(count)?;
+    self.emit_config_change(&context);
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = loaded.to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  // Empties the set ahead of a snapshot import, which brings its own, and marks
+  // the seed list as loaded so LoadValidIds can't bring removed ids back
+  pub(crate) fn clear_valid_ids(&self) -> Result<()> {
+    if self.valid_ids().count() > MAX_VALID_ID_BATCH {
+      return Err(anyhow!("Too many valid ids to clear in one call"));
+    }
+
+    self.valid_ids().drain()?;
+    self.valid_id_seed_cursor_pointer().set_value::<u128>(panda_ids::seed_id_count());
+
+    Ok(())
+  }
+
+  pub(crate) fn add_valid_id_range(&self, start: u128, length: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    self.add_valid_ids(&self.valid_id_range(start, length)?.collect::<Vec<u128>>())?;
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  pub(crate) fn remove_valid_id_range(&self, start: u128, length: u128) -> Result<CallResponse> {
+    self.only_owner()?;
+    let context = self.context()?;
+
+    let valid_ids = self.valid_ids();
+    for tx in self.valid_id_range(start, length)? {
+      if let Some(index) = valid_ids.find(&AlkaneId { block: PANDA_BLOCK, tx })? {
+        valid_ids.remove(index)?;
+      }
+    }
+    self.emit_config_change(&context);
+
+    Ok(CallResponse::forward(&context.incoming_alkanes))
+  }
+
+  // The total number of valid ids u128, then page `page` of their tx ids as
+  // u128s. Removals move ids around, so the set is in no particular order and
+  // should be paged through in one go.
+  pub(crate) fn get_valid_ids(&self, page: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let valid_ids = self.valid_ids();
+    let count = valid_ids.count();
+    let start = page.saturating_mul(VALID_ID_PAGE).min(count);
+    let end = start.saturating_add(VALID_ID_PAGE).min(count);
+
+    response.data = count.to_le_bytes().to_vec();
+    for index in start..end {
+      response.data.extend_from_slice(&valid_ids.get(index)?.tx.to_le_bytes());
+    }
+
+    Ok(response)
+  }
+
+  pub(crate) fn is_valid_id(&self, tx: u128) -> Result<bool> {
+    Ok(self.valid_ids().find(&AlkaneId { block: PANDA_BLOCK, tx })?.is_some())
+  }
+
+  // Skips ids that are already in the set
+  pub(crate) fn add_valid_ids(&self, ids: &[u128]) -> Result<()> {
+    let mut additions = Vec::new();
+    for tx in ids {
+      if !self.is_valid_id(*tx)? {
+        additions.push(AlkaneId { block: PANDA_BLOCK, tx: *tx });
+      }
+    }
+
+    self.valid_ids().extend(&additions)?;
+
+    Ok(())
+  }
+
+  fn valid_id_range(&self, start: u128, length: u128) -> Result<std::ops::Range<u128>> {
+    if length > MAX_VALID_ID_BATCH {
+      return Err(anyhow!("Too many ids in one batch"));
+    }
+
+    let end = start.checked_add(length)
+      .ok_or_else(|| anyhow!("Invalid id range"))?;
+
+    Ok(start..end)
+  }
+
+  pub(crate) fn valid_ids(&self) -> PandaStack {
+    PandaStack::new("/valid-ids")
+  }
+
+  fn load_seed_page(&self, count: u128) -> Result<u128> {
+    let mut cursor = self.valid_id_seed_cursor_pointer();
+    let loaded = cursor.get_value::<u128>();
+
+    let ids = panda_ids::seed_ids(loaded, count);
+    self.add_valid_ids(&ids)?;
+
+    let loaded = loaded + ids.len() as u128;
+    cursor.set_value::<u128>(loaded);
+
+    Ok(loaded)
+  }
+
+  fn valid_id_seed_cursor_pointer(&self) -> StoragePointer {
+    StoragePointer::from_keyword("/valid-id-seed/loaded")
+  }
+}
@@ -0,0 +1,156 @@
+use metashrew_support::index_pointer::KeyValuePointer;
+
+use alkanes_runtime::{runtime::AlkaneResponder, storage::StoragePointer};
+use alkanes_support::{
+  context::Context, id::AlkaneId,
+  parcel::{AlkaneTransfer, AlkaneTransferParcel}, response::CallResponse,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{alkane_id_to_bytes, stack::PandaStack, PandaRoll, RollOutcome};
+
+// Pandas parked in the contract on a caller's behalf. They stay out of the
+// prize pools and only move when their owner says so. Indexes shift when a
+// panda leaves, since the last panda takes its place.
+impl PandaRoll {
+  pub(crate) fn deposit_to_vault(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+
+    if context.incoming_alkanes.0.len() == 0 {
+      return Err(anyhow!("Must send at least 1 Panda"));
+    }
+
+    let vault = self.vault(&context.caller);
+    for alkane in context.incoming_alkanes.0.iter() {
+      if !self.accepts_panda(&alkane.id)? {
+        return Err(anyhow!("Invalid Panda ID"));
+      }
+
+      vault.push(&alkane.id)?;
+    }
+
+    let mut response = CallResponse::default();
+    response.data = vault.count().to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn withdraw_from_vault(&self, panda_index: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let panda = self.take_from_vault(&context.caller, panda_index)?;
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.alkanes.0.push(AlkaneTransfer {
+      id: panda,
+      value: 1u128,
+    });
+
+    Ok(response)
+  }
+
+  // Moves a vaulted panda to another player's vault without it leaving the contract
+  pub(crate) fn transfer_internal(&self, to_block: u128, to_tx: u128, panda_index: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let recipient = AlkaneId { block: to_block, tx: to_tx };
+
+    if recipient == context.caller {
+      return Err(anyhow!("Cannot transfer to yourself"));
+    }
+
+    let panda = self.take_from_vault(&context.caller, panda_index)?;
+    self.vault(&recipient).push(&panda)?;
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = alkane_id_to_bytes(&panda);
+
+    Ok(response)
+  }
+
+  // Stakes a vaulted panda and credits any winnings back to the vault. No panda
+  // moves, so several of these can run in one transaction and the caller's roll
+  // nonce keeps their entropy apart.
+  pub(crate) fn roll_from_vault(&self, risk: u128, panda_index: u128) -> Result<CallResponse> {
+    let context = self.context()?;
+    let txid = self.transaction_id()?;
+
+    let panda = self.take_from_vault(&context.caller, panda_index)?;
+    let roll_context = Context {
+      incoming_alkanes: AlkaneTransferParcel(vec![AlkaneTransfer { id: panda, value: 1u128 }]),
+      ..context.clone()
+    };
+
+    let max_multiplier = self.max_stake_multiplier(&roll_context, risk)?;
+    self.ensure_prizes_for_stake(&roll_context, max_multiplier - 1)?;
+
+    self.count_roll(&context.caller)?;
+
+    let (_, outcome, _) = self.draw_outcome(&roll_context, &txid, 0, risk, 0, false, true)?;
+
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+    response.data = outcome.multiplier().to_le_bytes().to_vec();
+
+    let vault = self.vault(&context.caller);
+    match outcome {
+      RollOutcome::Lose => {
+        self.forfeit_stake(&roll_context)?;
+        self.pay_consolation(&context, &mut response, 1)?;
+      }
+      RollOutcome::Push => {
+        vault.push(&panda)?;
+        self.pay_consolation(&context, &mut response, 1)?;
+      }
+      RollOutcome::Win(multiplier) => {
+        vault.push(&panda)?;
+
+        let mut winnings = CallResponse::default();
+        self.pay_prizes_for_stake(&roll_context, &mut winnings, multiplier - 1)?;
+        self.log_roll_prizes(&context.caller, &txid, &winnings.alkanes.0)?;
+        for alkane in winnings.alkanes.0.iter() {
+          vault.push(&alkane.id)?;
+        }
+      }
+    }
+
+    self.mint_receipt(&mut response, &txid, &outcome)?;
+
+    Ok(response)
+  }
+
+  pub(crate) fn get_my_vault_count(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    response.data = self.vault(&context.caller).count().to_le_bytes().to_vec();
+
+    Ok(response)
+  }
+
+  pub(crate) fn get_my_vault(&self) -> Result<CallResponse> {
+    let context = self.context()?;
+    let mut response = CallResponse::forward(&context.incoming_alkanes);
+
+    let vault = self.vault(&context.caller);
+    let mut data = Vec::new();
+    for i in 0..vault.count() {
+      data.extend(alkane_id_to_bytes(&vault.get(i)?));
+    }
+
+    response.data = data;
+
+    Ok(response)
+  }
+
+  pub(crate) fn take_from_vault(&self, owner: &AlkaneId, panda_index: u128) -> Result<AlkaneId> {
+    let vault = self.vault(owner);
+    if panda_index >= vault.count() {
+      return Err(anyhow!("No Panda at that vault index"));
+    }
+
+    vault.remove(panda_index)
+  }
+
+  pub(crate) fn vault(&self, owner: &AlkaneId) -> PandaStack {
+    PandaStack::at(&StoragePointer::from_keyword("/vaults/").select(&alkane_id_to_bytes(owner)))
+  }
+}